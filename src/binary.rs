@@ -0,0 +1,328 @@
+use nalgebra::DMatrix;
+
+use crate::{
+    brain::{Brain, ImportError},
+    compress,
+    model::Model,
+    nn::{ActivationFunc, WeightInit, NN},
+};
+
+// Layout, all little-endian:
+//   magic, version u16, flags u8, layer count u32, layer sizes u32 each,
+//   activations u8 each, mut_rate f32, mut_sigma f32, weight init u8 and its
+//   range f32 (from version 2), weights f32 column-major
+//   layer by layer, recurrent weights if flagged, metadata JSON length u32 and
+//   bytes, then a CRC-32 of everything before it
+// Quantized networks (from version 3) store each matrix as an f32 scale and
+// then i8 weights instead.
+pub const MAGIC: [u8; 4] = *b"GNN\x01";
+const VERSION: u16 = 3;
+const RECURRENT: u8 = 1;
+const QUANTIZED: u8 = 2;
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn put_matrix(out: &mut Vec<u8>, m: &DMatrix<f32>, quantized: bool) {
+    if quantized {
+        let s = compress::scale(m);
+        out.extend_from_slice(&s.to_le_bytes());
+        for x in m.iter() {
+            let q = if s == 0. { 0 } else { (x / s).round() as i8 };
+            out.push(q as u8);
+        }
+    } else {
+        for x in m.iter() {
+            out.extend_from_slice(&x.to_le_bytes());
+        }
+    }
+}
+
+pub fn encode(model: &Model) -> Result<Vec<u8>, String> {
+    let nn = match &model.brain {
+        Brain::NN(nn) => nn,
+        Brain::Neat(_) => return Err("NEAT genomes can only be saved as JSON".to_string()),
+    };
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    // Networks quantized with compress are stored at a quarter of the size
+    let quantized = nn
+        .weights
+        .iter()
+        .chain(&nn.recurrent)
+        .all(compress::is_quantized);
+    let mut flags = 0;
+    if !nn.recurrent.is_empty() {
+        flags |= RECURRENT;
+    }
+    if quantized {
+        flags |= QUANTIZED;
+    }
+    out.push(flags);
+    out.extend_from_slice(&(nn.config.len() as u32).to_le_bytes());
+    for &n in &nn.config {
+        out.extend_from_slice(&(n as u32).to_le_bytes());
+    }
+    for f in &nn.activ_funcs {
+        out.push(ActivationFunc::ALL.iter().position(|x| x == f).unwrap() as u8);
+    }
+    out.extend_from_slice(&nn.mut_rate.to_le_bytes());
+    out.extend_from_slice(&nn.mut_sigma.to_le_bytes());
+    out.push(nn.init.index() as u8);
+    let range = match nn.init {
+        WeightInit::Uniform(a) => a,
+        _ => 0.,
+    };
+    out.extend_from_slice(&range.to_le_bytes());
+    for m in nn.weights.iter().chain(&nn.recurrent) {
+        put_matrix(&mut out, m, quantized);
+    }
+    let meta = serde_json::to_vec(&model.meta).unwrap();
+    out.extend_from_slice(&(meta.len() as u32).to_le_bytes());
+    out.extend_from_slice(&meta);
+    out.extend_from_slice(&crc32(&out).to_le_bytes());
+    Ok(out)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ImportError> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or(ImportError::Corrupt("file is truncated".to_string()))?;
+        let s = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(s)
+    }
+
+    fn u8(&mut self) -> Result<u8, ImportError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ImportError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, ImportError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, ImportError> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn matrix(
+        &mut self,
+        rows: usize,
+        cols: usize,
+        quantized: bool,
+    ) -> Result<DMatrix<f32>, ImportError> {
+        if quantized {
+            let s = self.f32()?;
+            let data = self
+                .take(rows.saturating_mul(cols))?
+                .iter()
+                .map(|&q| q as i8 as f32 * s)
+                .collect();
+            return Ok(DMatrix::from_vec(rows, cols, data));
+        }
+        let data = self
+            .take(rows.saturating_mul(cols).saturating_mul(4))?
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+        Ok(DMatrix::from_vec(rows, cols, data))
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Model, ImportError> {
+    if bytes.len() < MAGIC.len() + 4 || !bytes.starts_with(&MAGIC) {
+        return Err(ImportError::Corrupt("not a binary model".to_string()));
+    }
+    let (body, sum) = bytes.split_at(bytes.len() - 4);
+    if crc32(body) != u32::from_le_bytes(sum.try_into().unwrap()) {
+        return Err(ImportError::Corrupt("checksum mismatch".to_string()));
+    }
+    let mut r = Reader {
+        bytes: body,
+        pos: MAGIC.len(),
+    };
+    let version = r.u16()?;
+    if version > VERSION {
+        return Err(ImportError::Version(version as u64));
+    }
+    let flags = r.u8()?;
+    let layers = r.u32()? as usize;
+    if layers < 2 {
+        return Err(ImportError::Shape(format!("{} layers", layers)));
+    }
+    let config = (0..layers)
+        .map(|_| r.u32().map(|n| n as usize))
+        .collect::<Result<Vec<_>, _>>()?;
+    if config.contains(&0) {
+        return Err(ImportError::Shape(format!("layer sizes {:?}", config)));
+    }
+    let activ_funcs = (1..layers)
+        .map(|_| {
+            let i = r.u8()? as usize;
+            ActivationFunc::ALL
+                .get(i)
+                .copied()
+                .ok_or(ImportError::Corrupt(format!("unknown activation {}", i)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut_rate = r.f32()?;
+    let mut_sigma = r.f32()?;
+    let init = if version >= 2 {
+        let i = r.u8()? as usize;
+        WeightInit::from_index(i, r.f32()?)
+    } else {
+        WeightInit::default()
+    };
+    // Every layer but the output one has a bias neuron
+    let rows = |i: usize| config[i + 1] - (i + 2 < layers) as usize;
+    let quantized = flags & QUANTIZED != 0;
+    let weights = (0..layers - 1)
+        .map(|i| r.matrix(rows(i), config[i], quantized))
+        .collect::<Result<Vec<_>, _>>()?;
+    let recurrent = if flags & RECURRENT != 0 {
+        (0..layers - 2)
+            .map(|i| r.matrix(rows(i), rows(i), quantized))
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        Vec::new()
+    };
+    let len = r.u32()? as usize;
+    let meta = serde_json::from_slice(r.take(len)?)?;
+    if r.pos != body.len() {
+        return Err(ImportError::Corrupt("trailing bytes".to_string()));
+    }
+
+    let nn = NN::from_parts(
+        config,
+        weights,
+        activ_funcs,
+        mut_rate,
+        mut_sigma,
+        recurrent,
+        init,
+    );
+    Ok(Model::new(Brain::NN(nn), meta))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Metadata;
+
+    fn model(recurrent: bool) -> Model {
+        let mut nn = NN::new(
+            vec![5, 6, 3, 4],
+            0.1,
+            vec![
+                ActivationFunc::Tanh,
+                ActivationFunc::ReLU,
+                ActivationFunc::Sigmoid,
+            ],
+            WeightInit::Uniform(0.5),
+        );
+        if recurrent {
+            nn.recurrent = vec![WeightInit::He.matrix(6, 6), WeightInit::He.matrix(3, 3)];
+        }
+        Model::new(Brain::NN(nn), Metadata::new(12, 3.5, 42, "test"))
+    }
+
+    fn nn(model: &Model) -> &NN {
+        match &model.brain {
+            Brain::NN(nn) => nn,
+            Brain::Neat(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn round_trips_float_weights() {
+        for recurrent in [false, true] {
+            let model = model(recurrent);
+            let decoded = decode(&encode(&model).unwrap()).ok().unwrap();
+            let (a, b) = (nn(&model), nn(&decoded));
+            assert_eq!(a.config, b.config);
+            assert_eq!(a.weights, b.weights);
+            assert_eq!(a.recurrent, b.recurrent);
+            assert_eq!(a.activ_funcs, b.activ_funcs);
+            assert_eq!((a.mut_rate, a.mut_sigma), (b.mut_rate, b.mut_sigma));
+            assert_eq!(a.init, b.init);
+            assert_eq!(decoded.meta.notes, "test");
+            assert_eq!(decoded.meta.generation, Some(12));
+        }
+    }
+
+    #[test]
+    fn round_trips_quantized_weights() {
+        let mut model = model(true);
+        let float_len = encode(&model).unwrap().len();
+        if let Brain::NN(nn) = &mut model.brain {
+            for m in nn.weights.iter_mut().chain(nn.recurrent.iter_mut()) {
+                let s = compress::scale(m);
+                m.iter_mut().for_each(|w| *w = (*w / s).round() * s);
+            }
+        }
+        let bytes = encode(&model).unwrap();
+        assert_ne!(bytes[MAGIC.len() + 2] & QUANTIZED, 0);
+        assert!(bytes.len() < float_len);
+        let decoded = decode(&bytes).ok().unwrap();
+        let (a, b) = (nn(&model), nn(&decoded));
+        for (x, y) in a
+            .weights
+            .iter()
+            .chain(&a.recurrent)
+            .zip(b.weights.iter().chain(&b.recurrent))
+        {
+            assert_eq!(x.shape(), y.shape());
+            assert!(x
+                .iter()
+                .zip(y.iter())
+                .all(|(p, q)| (p - q).abs() <= 1e-6 * p.abs().max(1.)));
+        }
+    }
+
+    #[test]
+    fn rejects_a_flipped_byte() {
+        let mut bytes = encode(&model(false)).unwrap();
+        bytes[40] ^= 0x10;
+        match decode(&bytes) {
+            Err(ImportError::Corrupt(e)) => assert_eq!(e, "checksum mismatch"),
+            _ => panic!("expected a checksum error"),
+        }
+    }
+
+    #[test]
+    fn rejects_a_truncated_file() {
+        // Cut off inside the weights but with a valid checksum, so that it is
+        // the reader that runs out of bytes
+        let bytes = encode(&model(false)).unwrap();
+        let mut cut = bytes[..bytes.len() / 2].to_vec();
+        cut.extend_from_slice(&crc32(&cut).to_le_bytes());
+        match decode(&cut) {
+            Err(ImportError::Corrupt(e)) => assert_eq!(e, "file is truncated"),
+            _ => panic!("expected a truncation error"),
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    model::{Model, VERSION},
+    neat::Neat,
+    nn::{layer_activs, ActivationFunc, Hover, NN},
+    player::{INPUTS, OUTPUTS},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Arch {
+    #[default]
+    Dense,
+    Recurrent,
+    Neat,
+}
+
+impl Arch {
+    pub const ALL: [Arch; 3] = [Arch::Dense, Arch::Recurrent, Arch::Neat];
+    pub const NAMES: [&'static str; 3] = ["Dense", "Recurrent", "NEAT"];
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Read(std::io::Error),
+    Parse(serde_json::Error),
+    // Binary file that is truncated or fails its checksum
+    Corrupt(String),
+    // Written by a newer version of the program
+    Version(u64),
+    // Weights that don't fit the layer sizes or nodes that don't exist
+    Shape(String),
+    // A valid network for a different number of inputs or outputs
+    Unsupported { inputs: usize, outputs: usize },
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::Read(e) => write!(f, "Unable to read file: {}", e),
+            ImportError::Parse(e) => write!(f, "Not a valid model: {}", e),
+            ImportError::Corrupt(e) => write!(f, "Corrupt model file: {}", e),
+            ImportError::Version(v) => write!(
+                f,
+                "Model file version {} is newer than the supported {}",
+                v, VERSION
+            ),
+            ImportError::Shape(e) => write!(f, "Malformed network: {}", e),
+            ImportError::Unsupported { inputs, outputs } => write!(
+                f,
+                "Network has {} inputs and {} outputs, expected {} and {}",
+                inputs, outputs, INPUTS, OUTPUTS
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<std::io::Error> for ImportError {
+    fn from(e: std::io::Error) -> Self {
+        ImportError::Read(e)
+    }
+}
+
+impl From<serde_json::Error> for ImportError {
+    fn from(e: serde_json::Error) -> Self {
+        ImportError::Parse(e)
+    }
+}
+
+// Any network that can pilot a ship. Serialized without a tag so that plain
+// `NN` model files keep loading.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Brain {
+    NN(NN),
+    Neat(Neat),
+}
+
+impl Brain {
+    pub fn arch(&self) -> Arch {
+        match self {
+            Brain::NN(nn) if !nn.recurrent.is_empty() => Arch::Recurrent,
+            Brain::NN(_) => Arch::Dense,
+            Brain::Neat(_) => Arch::Neat,
+        }
+    }
+
+    // Forgets any memory kept from previous frames
+    pub fn reset_state(&mut self) {
+        if let Brain::NN(nn) = self {
+            nn.reset_state();
+        }
+    }
+
+    // Hidden activations followed by the output one. NEAT genomes have a single
+    // hidden activation.
+    pub fn activ_funcs(&self) -> Vec<ActivationFunc> {
+        match self {
+            Brain::NN(nn) => nn.activ_funcs.to_owned(),
+            Brain::Neat(neat) => vec![neat.hidden_activ, neat.output_activ],
+        }
+    }
+
+    pub fn output_activ(&self) -> ActivationFunc {
+        *self.activ_funcs().last().unwrap()
+    }
+
+    // Takes one activation per hidden layer slot in `hlayers` plus the output one
+    pub fn set_activ_funcs(&mut self, hlayers: &[usize], activs: &[ActivationFunc]) {
+        match self {
+            Brain::NN(nn) => nn.activ_funcs = layer_activs(hlayers, activs),
+            Brain::Neat(neat) => {
+                neat.hidden_activ = activs[0];
+                neat.output_activ = *activs.last().unwrap();
+            }
+        }
+    }
+
+    pub fn mut_rate(&self) -> f32 {
+        match self {
+            Brain::NN(nn) => nn.mut_rate,
+            Brain::Neat(neat) => neat.mut_rate,
+        }
+    }
+
+    pub fn mut_sigma(&self) -> f32 {
+        match self {
+            Brain::NN(nn) => nn.mut_sigma,
+            Brain::Neat(neat) => neat.mut_sigma,
+        }
+    }
+
+    pub fn set_mutation(&mut self, rate: f32, sigma: f32) {
+        match self {
+            Brain::NN(nn) => {
+                nn.mut_rate = rate;
+                nn.mut_sigma = sigma;
+            }
+            Brain::Neat(neat) => {
+                neat.mut_rate = rate;
+                neat.mut_sigma = sigma;
+            }
+        }
+    }
+
+    // For NEAT genomes `a` should be the fitter parent
+    pub fn crossover(a: &Brain, b: &Brain) -> Self {
+        match (a, b) {
+            (Brain::NN(a), Brain::NN(b)) => Brain::NN(NN::crossover(a, b)),
+            (Brain::Neat(a), Brain::Neat(b)) => Brain::Neat(Neat::crossover(a, b)),
+            _ => panic!("Brain types not same."),
+        }
+    }
+
+    pub fn mutate(&mut self) {
+        match self {
+            Brain::NN(nn) => nn.mutate(),
+            Brain::Neat(neat) => neat.mutate(),
+        }
+    }
+
+    pub fn feed_forward_into(&mut self, inputs: &[f32], outputs: &mut [f32]) {
+        match self {
+            Brain::NN(nn) => nn.feed_forward_into(inputs, outputs),
+            Brain::Neat(neat) => outputs.copy_from_slice(&neat.feed_forward(inputs)),
+        }
+    }
+
+    pub fn draw(&self, width: f32, height: f32, inputs: &[f32], outputs: &[f32], bias: bool) {
+        match self {
+            Brain::NN(nn) => nn.draw(width, height, inputs, outputs, bias),
+            Brain::Neat(neat) => neat.draw(width, height, inputs, outputs, bias),
+        }
+    }
+
+    // The inspector only knows the fixed layer layout of dense networks
+    pub fn hover(&self, width: f32, height: f32, bias: bool, mouse: (f32, f32)) -> Option<Hover> {
+        match self {
+            Brain::NN(nn) => nn.hover(width, height, bias, mouse),
+            Brain::Neat(_) => None,
+        }
+    }
+
+    pub fn draw_inspector(
+        &self,
+        width: f32,
+        height: f32,
+        inputs: &[f32],
+        bias: bool,
+        mouse: (f32, f32),
+        selected: Option<(usize, usize)>,
+    ) {
+        if let Brain::NN(nn) = self {
+            nn.draw_inspector(width, height, inputs, bias, mouse, selected);
+        }
+    }
+
+    // Loads a model file of any version, dropping its metadata
+    pub fn import(path: &str) -> Result<Brain, ImportError> {
+        Ok(Model::import(path)?.brain)
+    }
+
+    // Checks what serde can't: that the network is well formed and fits the game
+    pub fn validate(&self) -> Result<(), ImportError> {
+        match self {
+            Brain::NN(nn) => {
+                if nn.config.len() < 2
+                    || nn.weights.len() != nn.config.len() - 1
+                    || nn.config.contains(&0)
+                {
+                    return Err(ImportError::Shape(format!(
+                        "{} weight matrices for layer sizes {:?}",
+                        nn.weights.len(),
+                        nn.config
+                    )));
+                }
+                for (i, w) in nn.weights.iter().enumerate() {
+                    // Every layer but the output one has a bias neuron
+                    let rows = nn.config[i + 1] - (i + 2 < nn.config.len()) as usize;
+                    if w.shape() != (rows, nn.config[i]) {
+                        return Err(ImportError::Shape(format!(
+                            "layer {} weights are {}x{}, expected {}x{}",
+                            i + 1,
+                            w.nrows(),
+                            w.ncols(),
+                            rows,
+                            nn.config[i]
+                        )));
+                    }
+                }
+                // The JSON reader checks these too, the binary and NumPy ones don't
+                if nn.activ_funcs.len() != nn.weights.len() {
+                    return Err(ImportError::Shape(format!(
+                        "{} activation functions for {} layers",
+                        nn.activ_funcs.len(),
+                        nn.weights.len()
+                    )));
+                }
+                if !nn.recurrent.is_empty() && nn.recurrent.len() != nn.weights.len() - 1 {
+                    return Err(ImportError::Shape(format!(
+                        "{} recurrent matrices for {} hidden layers",
+                        nn.recurrent.len(),
+                        nn.weights.len() - 1
+                    )));
+                }
+                for (i, (r, w)) in nn.recurrent.iter().zip(&nn.weights).enumerate() {
+                    if r.shape() != (w.nrows(), w.nrows()) {
+                        return Err(ImportError::Shape(format!(
+                            "layer {} recurrent weights are {}x{}, expected {}x{}",
+                            i + 1,
+                            r.nrows(),
+                            r.ncols(),
+                            w.nrows(),
+                            w.nrows()
+                        )));
+                    }
+                }
+            }
+            Brain::Neat(_) => {}
+        }
+        let (inputs, outputs) = self.io();
+        if (inputs, outputs) != (INPUTS, OUTPUTS) {
+            return Err(ImportError::Unsupported { inputs, outputs });
+        }
+        if let Brain::Neat(neat) = self {
+            neat.validate(INPUTS, OUTPUTS).map_err(ImportError::Shape)?;
+        }
+        Ok(())
+    }
+
+    fn io(&self) -> (usize, usize) {
+        match self {
+            Brain::NN(nn) => (nn.config[0] - 1, *nn.config.last().unwrap()),
+            Brain::Neat(neat) => (neat.inputs(), neat.outputs()),
+        }
+    }
+
+    // Loads every .json, .bin and .npz model in a directory. They must all be of the same kind,
+    // and dense networks must share the same config.
+    pub fn import_dir(path: &str) -> Result<Vec<Brain>, String> {
+        let mut paths = std::fs::read_dir(path)
+            .map_err(|e| format!("Unable to read {}: {}", path, e))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| {
+                p.extension()
+                    .is_some_and(|ext| ext == "json" || ext == "bin" || ext == "npz")
+            })
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        let mut brains: Vec<Brain> = Vec::new();
+        for p in &paths {
+            let brain = Brain::import(&p.to_string_lossy())
+                .map_err(|e| format!("{}: {}", p.display(), e))?;
+            if let Some(first) = brains.first() {
+                match (first, &brain) {
+                    (Brain::NN(a), Brain::NN(b))
+                        if a.config != b.config
+                            || a.recurrent.is_empty() != b.recurrent.is_empty() =>
+                    {
+                        return Err(format!(
+                            "{} has config {:?}{}, which is incompatible with {:?}{} from {}",
+                            p.display(),
+                            b.config,
+                            if b.recurrent.is_empty() {
+                                ""
+                            } else {
+                                " (recurrent)"
+                            },
+                            a.config,
+                            if a.recurrent.is_empty() {
+                                ""
+                            } else {
+                                " (recurrent)"
+                            },
+                            paths[0].display()
+                        ))
+                    }
+                    (Brain::NN(_), Brain::Neat(_)) | (Brain::Neat(_), Brain::NN(_)) => {
+                        return Err(format!(
+                            "{} and {} are different kinds of network",
+                            paths[0].display(),
+                            p.display()
+                        ))
+                    }
+                    _ => {}
+                }
+            }
+            brains.push(brain);
+        }
+        if brains.is_empty() {
+            return Err(format!("No .json, .bin or .npz models found in {}", path));
+        }
+        Ok(brains)
+    }
+}
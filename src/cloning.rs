@@ -0,0 +1,298 @@
+use std::fmt;
+
+use nalgebra::{DMatrix, DVector};
+use r::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    nn::{ActivationFunc, NN},
+    player::{Player, OUTPUTS},
+};
+extern crate rand as r;
+
+// Passes over a session when none is given
+pub const EPOCHS: usize = 100;
+const LEARNING_RATE: f32 = 0.05;
+const BATCH_SIZE: usize = 32;
+
+// One frame of human play: what the ship saw and the keys held down in answer
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Sample {
+    pub inputs: Vec<f32>,
+    pub keys: [bool; OUTPUTS],
+}
+
+// Every frame a human played with an asteroid in sight, across games
+#[derive(Default, Serialize, Deserialize)]
+pub struct Session {
+    pub samples: Vec<Sample>,
+}
+
+impl Session {
+    pub fn record(&mut self, player: &Player) {
+        self.samples.push(Sample {
+            inputs: player.inputs.clone(),
+            keys: player.keys,
+        });
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        std::fs::write(path, serde_json::to_string(self).unwrap())
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let json =
+            std::fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+        let session: Session =
+            serde_json::from_str(&json).map_err(|e| format!("Invalid session {}: {}", path, e))?;
+        if session.samples.is_empty() {
+            return Err(format!("{} has no samples", path));
+        }
+        Ok(session)
+    }
+}
+
+pub struct Fit {
+    pub samples: usize,
+    pub loss: f32,
+    // Share of keys the network presses the same way the human did
+    pub accuracy: f32,
+}
+
+impl fmt::Display for Fit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} samples, loss {:.3}, {:.1}% of keys matched",
+            self.samples,
+            self.loss,
+            self.accuracy * 100.
+        )
+    }
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1. / (1. + (-x).exp())
+}
+
+// Each layer's sums and activations, the latter with the bias neuron appended
+// for every layer but the output. The output sums are the key logits.
+fn forward(nn: &NN, inputs: &[f32]) -> (Vec<DVector<f32>>, Vec<DVector<f32>>) {
+    let last = nn.weights.len() - 1;
+    let mut x = DVector::from_iterator(inputs.len() + 1, inputs.iter().copied().chain([1.]));
+    let mut sums = vec![x.clone()];
+    let mut activations = vec![x.clone()];
+    for (i, w) in nn.weights.iter().enumerate() {
+        let z = w * &x;
+        x = if i < last {
+            let a = z.map(|v| nn.activ_funcs[i].apply(v));
+            DVector::from_iterator(a.len() + 1, a.iter().copied().chain([1.]))
+        } else {
+            z.clone()
+        };
+        sums.push(z);
+        activations.push(x.clone());
+    }
+    (sums, activations)
+}
+
+// Binary cross-entropy of one sample, summed over the keys, and its gradient
+// added into `grads`
+fn backward(nn: &NN, sample: &Sample, grads: &mut [DMatrix<f32>]) -> f32 {
+    let (sums, activations) = forward(nn, &sample.inputs);
+    let logits = sums.last().unwrap();
+    let mut loss = 0.;
+    let mut delta = DVector::from_fn(OUTPUTS, |j, _| {
+        let p = sigmoid(logits[j]).clamp(1e-7, 1. - 1e-7);
+        let y = if sample.keys[j] { 1. } else { 0. };
+        loss -= y * p.ln() + (1. - y) * (1. - p).ln();
+        p - y
+    });
+    for i in (0..nn.weights.len()).rev() {
+        grads[i] += &delta * activations[i].transpose();
+        if i > 0 {
+            // Drop the bias neuron, which has nothing feeding it
+            let back = nn.weights[i].transpose() * &delta;
+            delta = DVector::from_fn(sums[i].len(), |j, _| {
+                back[j] * nn.activ_funcs[i - 1].derivative(sums[i][j])
+            });
+        }
+    }
+    loss
+}
+
+// Fits a feed-forward network to press the keys the human pressed, by
+// minibatch gradient descent on the cross-entropy of each key. The output
+// layer ends up as a sigmoid, its biases shifted so a key is pressed in game
+// exactly when the fitted probability passes one half.
+pub fn train(nn: &mut NN, session: &Session, epochs: usize) -> Fit {
+    assert!(
+        nn.recurrent.is_empty(),
+        "Only feed-forward networks can be cloned."
+    );
+    let last = nn.weights.len() - 1;
+    nn.activ_funcs[last] = ActivationFunc::Sigmoid;
+    let mut order = (0..session.samples.len()).collect::<Vec<_>>();
+    let mut rng = r::thread_rng();
+    for _ in 0..epochs {
+        order.shuffle(&mut rng);
+        for batch in order.chunks(BATCH_SIZE) {
+            let mut grads = nn
+                .weights
+                .iter()
+                .map(|w| DMatrix::zeros(w.nrows(), w.ncols()))
+                .collect::<Vec<_>>();
+            for &k in batch {
+                backward(nn, &session.samples[k], &mut grads);
+            }
+            for (w, g) in nn.weights.iter_mut().zip(&grads) {
+                *w -= g * (LEARNING_RATE / batch.len() as f32);
+            }
+        }
+    }
+
+    let mut grads = nn
+        .weights
+        .iter()
+        .map(|w| DMatrix::zeros(w.nrows(), w.ncols()))
+        .collect::<Vec<_>>();
+    let loss = session
+        .samples
+        .iter()
+        .map(|s| backward(nn, s, &mut grads))
+        .sum::<f32>()
+        / (session.samples.len() * OUTPUTS) as f32;
+
+    let t = ActivationFunc::Sigmoid.threshold();
+    let bias = nn.weights[last].ncols() - 1;
+    for j in 0..OUTPUTS {
+        nn.weights[last][(j, bias)] += (t / (1. - t)).ln();
+    }
+    let matched = session
+        .samples
+        .iter()
+        .map(|s| {
+            let (_, activations) = forward(nn, &s.inputs);
+            let logits = activations.last().unwrap();
+            (0..OUTPUTS)
+                .filter(|&j| (sigmoid(logits[j]) > t) == s.keys[j])
+                .count()
+        })
+        .sum::<usize>();
+
+    Fit {
+        samples: session.samples.len(),
+        loss,
+        accuracy: matched as f32 / (session.samples.len() * OUTPUTS) as f32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nn::WeightInit;
+    use r::Rng;
+
+    fn nn() -> NN {
+        NN::new(
+            vec![3, 5, OUTPUTS],
+            0.05,
+            vec![ActivationFunc::Tanh, ActivationFunc::Sigmoid],
+            WeightInit::Uniform(1.),
+        )
+    }
+
+    // Keys that depend on the inputs, except the last which is pressed in
+    // 70% of frames whatever the ship sees
+    fn session() -> Session {
+        let mut rng = r::thread_rng();
+        let samples = (0..300)
+            .map(|k| {
+                let x = (0..3).map(|_| rng.gen_range(-1f32..1.)).collect::<Vec<_>>();
+                Sample {
+                    keys: [x[0] > 0., x[1] > x[2], x[0] + x[2] > 0.5, k % 10 < 7],
+                    inputs: x,
+                }
+            })
+            .collect();
+        Session { samples }
+    }
+
+    fn loss(nn: &NN, session: &Session) -> f32 {
+        let mut grads = nn
+            .weights
+            .iter()
+            .map(|w| DMatrix::zeros(w.nrows(), w.ncols()))
+            .collect::<Vec<_>>();
+        session
+            .samples
+            .iter()
+            .map(|s| backward(nn, s, &mut grads))
+            .sum::<f32>()
+            / (session.samples.len() * OUTPUTS) as f32
+    }
+
+    #[test]
+    fn gradient_matches_finite_differences() {
+        let mut nn = nn();
+        let sample = &session().samples[0];
+        let mut grads = nn
+            .weights
+            .iter()
+            .map(|w| DMatrix::zeros(w.nrows(), w.ncols()))
+            .collect::<Vec<_>>();
+        backward(&nn, sample, &mut grads);
+        let h = 1e-2;
+        for i in 0..nn.weights.len() {
+            for k in 0..nn.weights[i].len() {
+                let w = nn.weights[i][k];
+                let mut scratch = grads.clone();
+                nn.weights[i][k] = w + h;
+                let up = backward(&nn, sample, &mut scratch);
+                nn.weights[i][k] = w - h;
+                let down = backward(&nn, sample, &mut scratch);
+                nn.weights[i][k] = w;
+                let numeric = (up - down) / (2. * h);
+                assert!(
+                    (numeric - grads[i][k]).abs() < 1e-2 * grads[i][k].abs().max(1.),
+                    "layer {} weight {}: {} vs {}",
+                    i,
+                    k,
+                    numeric,
+                    grads[i][k]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn training_lowers_the_loss() {
+        let session = session();
+        let mut nn = nn();
+        let before = loss(&nn, &session);
+        let fit = train(&mut nn, &session, EPOCHS);
+        assert!(fit.loss < before * 0.7, "{} -> {}", before, fit.loss);
+        assert!(fit.accuracy > 0.8, "{}", fit);
+    }
+
+    #[test]
+    fn keys_pressed_when_more_likely_than_not() {
+        // The last key is fitted to about 0.7, below the sigmoid threshold of
+        // 0.85, so only the bias shift keeps it pressed in game as it was in
+        // most frames
+        let session = session();
+        let mut nn = nn();
+        train(&mut nn, &session, EPOCHS);
+        let mut outputs = [0.; OUTPUTS];
+        let threshold = ActivationFunc::Sigmoid.threshold();
+        let pressed = session
+            .samples
+            .iter()
+            .filter(|s| {
+                nn.feed_forward_into(&s.inputs, &mut outputs);
+                outputs[OUTPUTS - 1] > threshold
+            })
+            .count();
+        assert!(pressed > session.samples.len() * 9 / 10, "{}", pressed);
+    }
+}
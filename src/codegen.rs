@@ -0,0 +1,373 @@
+use std::fmt::Write;
+
+use crate::nn::{ActivationFunc, NN};
+
+// Each layer becomes a loop over a constant row-major array, summing in the
+// same order as NN::feed_forward_with so the results match it bit for bit
+
+// Shortest literal that reads back as the same f32
+fn float(x: f32, suffix: &str) -> String {
+    let s = format!("{:?}", x);
+    if s.contains(['.', 'e']) {
+        format!("{}{}", s, suffix)
+    } else {
+        format!("{}.0{}", s, suffix)
+    }
+}
+
+fn rust_activ(f: ActivationFunc) -> &'static str {
+    match f {
+        ActivationFunc::ReLU => "z.max(0.)",
+        ActivationFunc::Sigmoid => "1. / (1. + (-z).exp())",
+        ActivationFunc::Tanh => "z.tanh()",
+        ActivationFunc::LeakyReLU => "z.max(0.01 * z)",
+        ActivationFunc::Elu => "if z > 0. { z } else { z.exp() - 1. }",
+        ActivationFunc::Softsign => "z / (1. + z.abs())",
+        ActivationFunc::Gaussian => "(-z * z).exp()",
+        ActivationFunc::Sine => "z.sin()",
+        ActivationFunc::Step => "if z > 0. { 1. } else { 0. }",
+        ActivationFunc::Identity => "z",
+        ActivationFunc::Swish => "z / (1. + (-z).exp())",
+    }
+}
+
+fn c_activ(f: ActivationFunc) -> &'static str {
+    match f {
+        ActivationFunc::ReLU => "fmaxf(z, 0.f)",
+        ActivationFunc::Sigmoid => "1.f / (1.f + expf(-z))",
+        ActivationFunc::Tanh => "tanhf(z)",
+        ActivationFunc::LeakyReLU => "fmaxf(z, 0.01f * z)",
+        ActivationFunc::Elu => "z > 0.f ? z : expf(z) - 1.f",
+        ActivationFunc::Softsign => "z / (1.f + fabsf(z))",
+        ActivationFunc::Gaussian => "expf(-z * z)",
+        ActivationFunc::Sine => "sinf(z)",
+        ActivationFunc::Step => "z > 0.f ? 1.f : 0.f",
+        ActivationFunc::Identity => "z",
+        ActivationFunc::Swish => "z / (1.f + expf(-z))",
+    }
+}
+
+// Rows of a matrix as nested braces or brackets
+fn rows(m: &nalgebra::DMatrix<f32>, open: &str, close: &str, suffix: &str) -> String {
+    let mut s = String::new();
+    for r in m.row_iter() {
+        let row = r.iter().map(|&x| float(x, suffix)).collect::<Vec<_>>();
+        writeln!(s, "    {}{}{},", open, row.join(", "), close).unwrap();
+    }
+    s
+}
+
+fn sizes(nn: &NN) -> String {
+    let last = nn.config.len() - 1;
+    let sizes = nn
+        .config
+        .iter()
+        .enumerate()
+        .map(|(i, &n)| (n - (i < last) as usize).to_string())
+        .collect::<Vec<_>>();
+    sizes.join(", ")
+}
+
+pub fn rust(nn: &NN) -> String {
+    let (inputs, outputs) = (nn.config[0] - 1, *nn.config.last().unwrap());
+    let last = nn.weights.len() - 1;
+    let recurrent = !nn.recurrent.is_empty();
+    let mut s = String::new();
+    writeln!(
+        s,
+        "// Generated by genetic. Layer sizes without bias neurons: {}",
+        sizes(nn)
+    )
+    .unwrap();
+    writeln!(s, "#![allow(dead_code, clippy::all)]\n").unwrap();
+    writeln!(s, "pub const INPUTS: usize = {};", inputs).unwrap();
+    writeln!(s, "pub const OUTPUTS: usize = {};\n", outputs).unwrap();
+    writeln!(s, "// One row per neuron, the last column being the bias").unwrap();
+    for (i, w) in nn.weights.iter().enumerate() {
+        writeln!(s, "const W{}: [[f32; {}]; {}] = [", i, w.ncols(), w.nrows()).unwrap();
+        s += &rows(w, "[", "]", "");
+        s += "];\n";
+    }
+    for (i, r) in nn.recurrent.iter().enumerate() {
+        writeln!(s, "const R{}: [[f32; {}]; {}] = [", i, r.ncols(), r.nrows()).unwrap();
+        s += &rows(r, "[", "]", "");
+        s += "];\n";
+    }
+
+    if recurrent {
+        s += "\n// Each hidden layer's activations from the previous call\n";
+        s += "#[derive(Clone, Debug, Default)]\npub struct State {\n";
+        for (i, r) in nn.recurrent.iter().enumerate() {
+            writeln!(s, "    pub h{}: [f32; {}],", i, r.nrows()).unwrap();
+        }
+        s += "}\n\n";
+        writeln!(
+            s,
+            "pub fn predict(inputs: &[f32; {}], state: &mut State) -> [f32; {}] {{",
+            inputs, outputs
+        )
+        .unwrap();
+    } else {
+        writeln!(
+            s,
+            "\npub fn predict(inputs: &[f32; {}]) -> [f32; {}] {{",
+            inputs, outputs
+        )
+        .unwrap();
+    }
+    writeln!(s, "    let mut a0 = [1f32; {}];", nn.config[0]).unwrap();
+    writeln!(s, "    a0[..{}].copy_from_slice(inputs);", inputs).unwrap();
+    for (i, w) in nn.weights.iter().enumerate() {
+        let n = if i == last { w.nrows() } else { w.nrows() + 1 };
+        let fill = if i == last { 0 } else { 1 };
+        writeln!(s, "    let mut a{} = [{}f32; {}];", i + 1, fill, n).unwrap();
+        writeln!(s, "    for j in 0..{} {{", w.nrows()).unwrap();
+        s += "        let mut z = 0f32;\n";
+        writeln!(s, "        for k in 0..{} {{", w.ncols()).unwrap();
+        writeln!(s, "            z += W{}[j][k] * a{}[k];", i, i).unwrap();
+        s += "        }\n";
+        if let Some(r) = nn.recurrent.get(i) {
+            s += "        let mut rz = 0f32;\n";
+            writeln!(s, "        for k in 0..{} {{", r.ncols()).unwrap();
+            writeln!(s, "            rz += R{}[j][k] * state.h{}[k];", i, i).unwrap();
+            s += "        }\n";
+            s += "        z += rz;\n";
+        }
+        writeln!(
+            s,
+            "        a{}[j] = {};",
+            i + 1,
+            rust_activ(nn.activ_funcs[i])
+        )
+        .unwrap();
+        s += "    }\n";
+        if i < nn.recurrent.len() {
+            writeln!(
+                s,
+                "    state.h{}.copy_from_slice(&a{}[..{}]);",
+                i,
+                i + 1,
+                w.nrows()
+            )
+            .unwrap();
+        }
+    }
+    writeln!(s, "    a{}\n}}", last + 1).unwrap();
+    s
+}
+
+pub fn c(nn: &NN) -> String {
+    let (inputs, outputs) = (nn.config[0] - 1, *nn.config.last().unwrap());
+    let last = nn.weights.len() - 1;
+    let recurrent = !nn.recurrent.is_empty();
+    let mut s = String::new();
+    writeln!(
+        s,
+        "/* Generated by genetic. Layer sizes without bias neurons: {}",
+        sizes(nn)
+    )
+    .unwrap();
+    s += "   Build with -ffp-contract=off for results identical to the app. */\n";
+    s += "#ifndef GENETIC_PREDICT_H\n#define GENETIC_PREDICT_H\n\n#include <math.h>\n\n";
+    writeln!(
+        s,
+        "#define INPUTS {}\n#define OUTPUTS {}\n",
+        inputs, outputs
+    )
+    .unwrap();
+    s += "/* One row per neuron, the last column being the bias */\n";
+    for (i, w) in nn.weights.iter().enumerate() {
+        writeln!(
+            s,
+            "static const float W{}[{}][{}] = {{",
+            i,
+            w.nrows(),
+            w.ncols()
+        )
+        .unwrap();
+        s += &rows(w, "{", "}", "f");
+        s += "};\n";
+    }
+    for (i, r) in nn.recurrent.iter().enumerate() {
+        writeln!(
+            s,
+            "static const float R{}[{}][{}] = {{",
+            i,
+            r.nrows(),
+            r.ncols()
+        )
+        .unwrap();
+        s += &rows(r, "{", "}", "f");
+        s += "};\n";
+    }
+
+    if recurrent {
+        s += "\n/* Each hidden layer's activations from the previous call, zeroed to start */\n";
+        s += "typedef struct {\n";
+        for (i, r) in nn.recurrent.iter().enumerate() {
+            writeln!(s, "    float h{}[{}];", i, r.nrows()).unwrap();
+        }
+        s += "} predict_state;\n\n";
+        writeln!(
+            s,
+            "static inline void predict(const float inputs[{}], float outputs[{}], predict_state *state) {{",
+            inputs, outputs
+        )
+        .unwrap();
+    } else {
+        writeln!(
+            s,
+            "\nstatic inline void predict(const float inputs[{}], float outputs[{}]) {{",
+            inputs, outputs
+        )
+        .unwrap();
+    }
+    s += "    int j, k;\n";
+    writeln!(s, "    float a0[{}];", nn.config[0]).unwrap();
+    for (i, w) in nn.weights.iter().enumerate().take(last) {
+        writeln!(s, "    float a{}[{}];", i + 1, w.nrows() + 1).unwrap();
+    }
+    writeln!(s, "    for (k = 0; k < {}; k++) a0[k] = inputs[k];", inputs).unwrap();
+    writeln!(s, "    a0[{}] = 1.f;", inputs).unwrap();
+    for (i, w) in nn.weights.iter().enumerate() {
+        let out = if i == last {
+            "outputs".to_string()
+        } else {
+            format!("a{}", i + 1)
+        };
+        writeln!(s, "    for (j = 0; j < {}; j++) {{", w.nrows()).unwrap();
+        s += "        float z = 0.f;\n";
+        writeln!(
+            s,
+            "        for (k = 0; k < {}; k++) z += W{}[j][k] * a{}[k];",
+            w.ncols(),
+            i,
+            i
+        )
+        .unwrap();
+        if let Some(r) = nn.recurrent.get(i) {
+            s += "        float rz = 0.f;\n";
+            writeln!(
+                s,
+                "        for (k = 0; k < {}; k++) rz += R{}[j][k] * state->h{}[k];",
+                r.ncols(),
+                i,
+                i
+            )
+            .unwrap();
+            s += "        z += rz;\n";
+        }
+        writeln!(s, "        {}[j] = {};", out, c_activ(nn.activ_funcs[i])).unwrap();
+        s += "    }\n";
+        if i < last {
+            writeln!(s, "    a{}[{}] = 1.f;", i + 1, w.nrows()).unwrap();
+        }
+        if i < nn.recurrent.len() {
+            writeln!(
+                s,
+                "    for (k = 0; k < {}; k++) state->h{}[k] = a{}[k];",
+                w.nrows(),
+                i,
+                i + 1
+            )
+            .unwrap();
+        }
+    }
+    s += "}\n\n#endif\n";
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use macroquad::rand::gen_range;
+
+    use super::*;
+    use crate::nn::WeightInit;
+
+    const STEPS: usize = 4;
+
+    // Every activation, a few per network, each network both without and
+    // with recurrent weights
+    fn networks() -> Vec<NN> {
+        let mut nets = Vec::new();
+        for activs in ActivationFunc::ALL.chunks(3) {
+            let mut config = vec![5];
+            config.extend(vec![6; activs.len() - 1]);
+            config.push(3);
+            let nn = NN::new(config, 0.1, activs.to_vec(), WeightInit::Uniform(1.));
+            let mut recurrent = nn.clone();
+            recurrent.add_recurrence();
+            nets.push(nn);
+            nets.push(recurrent);
+        }
+        nets
+    }
+
+    #[test]
+    fn rust_matches_feed_forward() {
+        let mut nets = networks();
+        let inputs = (0..STEPS)
+            .map(|_| (0..5).map(|_| gen_range(-2f32, 2.)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        // A program printing the bits of every output of every network
+        let dir = std::env::temp_dir().join(format!("genetic-codegen-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut main = String::new();
+        for (n, nn) in nets.iter().enumerate() {
+            std::fs::write(dir.join(format!("n{}.rs", n)), rust(nn)).unwrap();
+            writeln!(main, "mod n{};", n).unwrap();
+        }
+        main += "\nfn main() {\n";
+        for (n, nn) in nets.iter().enumerate() {
+            let state = if nn.recurrent.is_empty() {
+                String::new()
+            } else {
+                writeln!(main, "    let mut s{} = n{}::State::default();", n, n).unwrap();
+                format!(", &mut s{}", n)
+            };
+            for x in &inputs {
+                let x = x
+                    .iter()
+                    .map(|v| format!("f32::from_bits({})", v.to_bits()))
+                    .collect::<Vec<_>>();
+                let call = format!("n{}::predict(&[{}]{})", n, x.join(", "), state);
+                writeln!(
+                    main,
+                    "    for y in {} {{ print!(\"{{}} \", y.to_bits()); }}",
+                    call
+                )
+                .unwrap();
+            }
+            main += "    println!();\n";
+        }
+        main += "}\n";
+        std::fs::write(dir.join("main.rs"), main).unwrap();
+        let bin = dir.join("predict");
+        let status = Command::new("rustc")
+            .args(["--edition", "2021", "-O", "-o"])
+            .arg(&bin)
+            .arg(dir.join("main.rs"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let out = Command::new(&bin).output().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let out = String::from_utf8(out.stdout).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), nets.len());
+        for (nn, line) in nets.iter_mut().zip(lines) {
+            let mut expected = Vec::new();
+            for x in &inputs {
+                let mut y = [0.; 3];
+                nn.feed_forward_into(x, &mut y);
+                expected.extend(y.iter().map(|v| v.to_bits().to_string()));
+            }
+            let got = line.split_whitespace().collect::<Vec<_>>();
+            assert_eq!(got, expected, "{:?}", nn.activ_funcs);
+        }
+    }
+}
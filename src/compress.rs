@@ -0,0 +1,188 @@
+use std::fmt;
+
+use nalgebra::DMatrix;
+
+use crate::{brain::Brain, nn::NN, world::World};
+
+// Fewest weights tried on their own when pruning by impact, every trial
+// playing all the episodes
+const CANDIDATES: usize = 32;
+
+pub enum Step {
+    // Zero every weight smaller in magnitude than this
+    Magnitude(f32),
+    // Zero this many weights, the ones whose removal costs the least fitness
+    Impact(usize),
+    // Round every weight to one of 255 levels per layer
+    Quantize,
+}
+
+pub struct Report {
+    pub changed: usize,
+    pub before: f32,
+    pub after: f32,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} weights changed, fitness {:.2} -> {:.2} ({:+.2})",
+            self.changed,
+            self.before,
+            self.after,
+            self.after - self.before
+        )
+    }
+}
+
+// The int8 step size of a layer, so that its largest weight maps to 127
+pub fn scale(m: &DMatrix<f32>) -> f32 {
+    m.amax() / 127.
+}
+
+// Whether every weight of a layer already sits on its int8 grid
+pub fn is_quantized(m: &DMatrix<f32>) -> bool {
+    let s = scale(m);
+    s == 0. || m.iter().all(|w| (w / s - (w / s).round()).abs() < 1e-3)
+}
+
+fn matrices(nn: &mut NN) -> impl Iterator<Item = &mut DMatrix<f32>> {
+    nn.weights.iter_mut().chain(nn.recurrent.iter_mut())
+}
+
+fn prune_magnitude(nn: &mut NN, threshold: f32) -> usize {
+    let mut changed = 0;
+    for w in matrices(nn).flat_map(|m| m.iter_mut()) {
+        if *w != 0. && w.abs() < threshold {
+            *w = 0.;
+            changed += 1;
+        }
+    }
+    changed
+}
+
+// Tries removing each of the smallest weights on its own, then removes the
+// `count` that hurt the least together. Only twice as many weights as asked
+// for, and at least CANDIDATES, are tried so large networks don't take ages.
+fn prune_impact(nn: &mut NN, count: usize, episodes: u32, seed: u64) -> usize {
+    let base = World::evaluate(&Brain::NN(nn.clone()), episodes, seed);
+    let mut genome = nn.genome();
+    let mut candidates = (0..genome.len())
+        .filter(|&k| genome[k] != 0.)
+        .collect::<Vec<_>>();
+    candidates.sort_by(|&a, &b| genome[a].abs().total_cmp(&genome[b].abs()));
+    candidates.truncate(CANDIDATES.max(count.saturating_mul(2)));
+    let mut impact = Vec::new();
+    for k in candidates {
+        let w = genome[k];
+        genome[k] = 0.;
+        let trial = NN::from_genome(
+            nn.config.clone(),
+            nn.activ_funcs.clone(),
+            !nn.recurrent.is_empty(),
+            &genome,
+            nn.mut_rate,
+            nn.mut_sigma,
+        )
+        .unwrap();
+        impact.push((base - World::evaluate(&Brain::NN(trial), episodes, seed), k));
+        genome[k] = w;
+    }
+    impact.sort_by(|a, b| a.0.total_cmp(&b.0));
+    impact.truncate(count);
+    for &(_, k) in &impact {
+        genome[k] = 0.;
+    }
+    nn.set_genome(&genome);
+    impact.len()
+}
+
+fn quantize(nn: &mut NN) -> usize {
+    let mut changed = 0;
+    for m in matrices(nn) {
+        let s = scale(m);
+        if s == 0. {
+            continue;
+        }
+        for w in m.iter_mut() {
+            let q = (*w / s).round() * s;
+            if q != *w {
+                *w = q;
+                changed += 1;
+            }
+        }
+    }
+    changed
+}
+
+// Applies a step and measures the fitness before and after it over the same
+// episodes
+pub fn apply(nn: &mut NN, step: Step, episodes: u32, seed: u64) -> Report {
+    let before = World::evaluate(&Brain::NN(nn.clone()), episodes, seed);
+    let changed = match step {
+        Step::Magnitude(threshold) => prune_magnitude(nn, threshold),
+        Step::Impact(count) => prune_impact(nn, count, episodes, seed),
+        Step::Quantize => quantize(nn),
+    };
+    let after = World::evaluate(&Brain::NN(nn.clone()), episodes, seed);
+    Report {
+        changed,
+        before,
+        after,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nn::{ActivationFunc, WeightInit};
+
+    fn nn() -> NN {
+        let mut nn = NN::new(
+            vec![5, 6, 4],
+            0.05,
+            vec![ActivationFunc::Tanh, ActivationFunc::Sigmoid],
+            WeightInit::Uniform(1.),
+        );
+        nn.add_recurrence();
+        nn
+    }
+
+    #[test]
+    fn prunes_exactly_the_small_weights() {
+        let mut nn = nn();
+        let before = nn.genome();
+        let changed = prune_magnitude(&mut nn, 0.3);
+        let after = nn.genome();
+        assert_eq!(
+            changed,
+            before.iter().filter(|w| **w != 0. && w.abs() < 0.3).count()
+        );
+        for (b, a) in before.iter().zip(&after) {
+            assert_eq!(*a, if b.abs() < 0.3 { 0. } else { *b });
+        }
+        assert_eq!(prune_magnitude(&mut nn, 0.3), 0);
+    }
+
+    #[test]
+    fn quantizes_within_half_a_step() {
+        let mut nn = nn();
+        let before = nn.clone();
+        quantize(&mut nn);
+        for (b, a) in before
+            .weights
+            .iter()
+            .chain(&before.recurrent)
+            .zip(nn.weights.iter().chain(&nn.recurrent))
+        {
+            let s = scale(b);
+            assert!(is_quantized(a));
+            assert!(!is_quantized(b));
+            for (x, y) in b.iter().zip(a.iter()) {
+                assert!((x - y).abs() <= s / 2. + 1e-6, "{} -> {}", x, y);
+            }
+        }
+        assert_eq!(quantize(&mut nn), 0);
+    }
+}
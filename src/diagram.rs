@@ -0,0 +1,238 @@
+use crate::{
+    nn::NN,
+    player::{INPUT_NAMES, OUTPUT_NAMES},
+};
+
+const WIDTH: f32 = 800.;
+const HEIGHT: f32 = 500.;
+
+// Red for negative and white for positive weights, fading out towards 0 as in
+// NN::draw, and thicker the larger they are
+fn color(w: f32) -> String {
+    let alpha = (w.abs().min(1.) * 255.) as u8;
+    if w < 0. {
+        format!("#ff0000{:02x}", alpha)
+    } else {
+        format!("#ffffff{:02x}", alpha)
+    }
+}
+
+fn thickness(w: f32) -> f32 {
+    0.5 + 2. * w.abs().min(2.)
+}
+
+fn label(nn: &NN, i: usize, j: usize) -> String {
+    let last = nn.config.len() - 1;
+    if i < last && j + 1 == nn.config[i] {
+        "1".to_string()
+    } else if i == 0 {
+        INPUT_NAMES.get(j).unwrap_or(&"").to_string()
+    } else if i == last {
+        OUTPUT_NAMES.get(j).unwrap_or(&"").to_string()
+    } else {
+        String::new()
+    }
+}
+
+// Recurrent weights are drawn as dashed edges within a layer
+pub fn to_dot(nn: &NN) -> String {
+    let mut dot = String::from(
+        "digraph network {\n    rankdir=LR;\n    bgcolor=black;\n    splines=line;\n    nodesep=0.3;\n    ranksep=1.5;\n",
+    );
+    dot += "    node [shape=circle, width=0.3, fixedsize=true, style=filled, fillcolor=black, color=white, fontcolor=white, fontsize=9];\n";
+    let last = nn.config.len() - 1;
+    for (i, &size) in nn.config.iter().enumerate() {
+        dot += "    { rank=same;";
+        for j in 0..size {
+            dot += &format!(" n{}_{};", i, j);
+        }
+        dot += " }\n";
+        for j in 0..size {
+            let label = label(nn, i, j);
+            // Long names go beside the neuron rather than in it
+            if i == 0 || i == last {
+                dot += &format!("    n{}_{} [label=\"\", xlabel=\"{}\"];\n", i, j, label);
+            } else {
+                dot += &format!("    n{}_{} [label=\"{}\"];\n", i, j, label);
+            }
+        }
+    }
+    for (i, w) in nn.weights.iter().enumerate() {
+        for j in 0..w.nrows() {
+            for k in 0..w.ncols() {
+                let w = w[(j, k)];
+                dot += &format!(
+                    "    n{}_{} -> n{}_{} [color=\"{}\", penwidth={:.2}, arrowhead=none, tooltip=\"{:.4}\"];\n",
+                    i,
+                    k,
+                    i + 1,
+                    j,
+                    color(w),
+                    thickness(w),
+                    w
+                );
+            }
+        }
+    }
+    for (i, r) in nn.recurrent.iter().enumerate() {
+        for j in 0..r.nrows() {
+            for k in 0..r.ncols() {
+                let w = r[(j, k)];
+                dot += &format!(
+                    "    n{}_{} -> n{}_{} [color=\"{}\", penwidth={:.2}, style=dashed, constraint=false, tooltip=\"{:.4}\"];\n",
+                    i + 1,
+                    k,
+                    i + 1,
+                    j,
+                    color(w),
+                    thickness(w),
+                    w
+                );
+            }
+        }
+    }
+    dot += "}\n";
+    dot
+}
+
+// The same picture as the brain panel with the bias neurons shown, minus the
+// live activations. Hovering over a connection shows its weight.
+pub fn to_svg(nn: &NN) -> String {
+    let layout = nn.layout(WIDTH * 0.7, HEIGHT, true);
+    let last = layout.len() - 1;
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"{} {} {} {}\" font-family=\"sans-serif\" font-size=\"12\">\n",
+        WIDTH,
+        HEIGHT,
+        -WIDTH * 0.5,
+        -HEIGHT * 0.5,
+        WIDTH,
+        HEIGHT
+    );
+    svg += &format!(
+        "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"black\" stroke=\"white\" stroke-width=\"2\"/>\n",
+        -WIDTH * 0.5,
+        -HEIGHT * 0.5,
+        WIDTH,
+        HEIGHT
+    );
+    for (i, w) in nn.weights.iter().enumerate() {
+        for (k, p1) in layout[i].iter().enumerate() {
+            for (j, p2) in layout[i + 1].iter().take(w.nrows()).enumerate() {
+                let w = w[(j, k)];
+                let c = if w < 0. { "red" } else { "white" };
+                svg += &format!(
+                    "  <line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"{}\" stroke-opacity=\"{:.3}\" stroke-width=\"{:.2}\"><title>{:.4}</title></line>\n",
+                    p1.0,
+                    p1.1,
+                    p2.0,
+                    p2.1,
+                    c,
+                    w.abs().min(1.),
+                    thickness(w),
+                    w
+                );
+            }
+        }
+    }
+    for (i, layer) in layout.iter().enumerate() {
+        for (j, p) in layer.iter().enumerate() {
+            svg += &format!(
+                "  <circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"9\" fill=\"black\" stroke=\"white\" stroke-width=\"2\"/>\n",
+                p.0, p.1
+            );
+            let label = label(nn, i, j);
+            let (x, anchor) = match i {
+                0 => (p.0 - 14., "end"),
+                _ if i == last => (p.0 + 14., "start"),
+                _ => (p.0, "middle"),
+            };
+            if !label.is_empty() {
+                svg += &format!(
+                    "  <text x=\"{:.1}\" y=\"{:.1}\" fill=\"white\" text-anchor=\"{}\">{}</text>\n",
+                    x,
+                    p.1 + 4.,
+                    anchor,
+                    label
+                );
+            }
+        }
+    }
+    let (x, y) = (WIDTH * 0.8 * 0.47, HEIGHT * 0.8 * 0.47);
+    svg += &format!(
+        "  <rect x=\"{:.1}\" y=\"{:.1}\" width=\"10\" height=\"10\" fill=\"red\"/>\n  <text x=\"{:.1}\" y=\"{:.1}\" fill=\"white\">-ve</text>\n",
+        x,
+        y,
+        x + 20.,
+        y + 10.
+    );
+    svg += &format!(
+        "  <rect x=\"{:.1}\" y=\"{:.1}\" width=\"10\" height=\"10\" fill=\"white\"/>\n  <text x=\"{:.1}\" y=\"{:.1}\" fill=\"white\">+ve</text>\n",
+        x,
+        y + 20.,
+        x + 20.,
+        y + 30.
+    );
+    svg += "</svg>\n";
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nn::{ActivationFunc, WeightInit};
+
+    fn nn(recurrent: bool) -> NN {
+        let mut nn = NN::new(
+            vec![2, 3, 2],
+            0.05,
+            vec![ActivationFunc::Tanh, ActivationFunc::Sigmoid],
+            WeightInit::Uniform(1.),
+        );
+        nn.weights[0][(0, 2)] = 0.25;
+        if recurrent {
+            nn.add_recurrence();
+            nn.recurrent[0][(1, 2)] = -0.5;
+        }
+        nn
+    }
+
+    #[test]
+    fn recurrent_weights_are_dashed() {
+        let dot = to_dot(&nn(false));
+        assert!(dot.starts_with("digraph network {"));
+        assert!(dot.trim_end().ends_with('}'));
+        // 3 x 3 weights into the hidden layer and 2 x 4 into the outputs
+        assert_eq!(dot.matches(" -> ").count(), 17);
+        assert!(!dot.contains("dashed"));
+        assert!(dot.contains(
+            "n0_2 -> n1_0 [color=\"#ffffff3f\", penwidth=1.00, arrowhead=none, tooltip=\"0.2500\"]"
+        ));
+        assert!(dot.contains("n0_2 [label=\"\", xlabel=\"1\"]"));
+
+        let dot = to_dot(&nn(true));
+        assert_eq!(dot.matches(" -> ").count(), 17 + 9);
+        assert_eq!(dot.matches("style=dashed").count(), 9);
+        assert!(dot.contains(
+            "n1_2 -> n1_1 [color=\"#ff00007f\", penwidth=1.50, style=dashed, constraint=false, tooltip=\"-0.5000\"]"
+        ));
+    }
+
+    #[test]
+    fn svg_draws_every_weight_and_neuron() {
+        let svg = to_svg(&nn(false));
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+        assert!(svg.ends_with("</svg>\n"));
+        assert_eq!(svg.matches("<line ").count(), 17);
+        // With the bias neurons of the input and hidden layers
+        assert_eq!(svg.matches("<circle ").count(), 3 + 4 + 2);
+        assert!(svg.contains("<title>0.2500</title>"));
+        for name in INPUT_NAMES
+            .iter()
+            .take(2)
+            .chain(OUTPUT_NAMES.iter().take(2))
+        {
+            assert!(svg.contains(&format!(">{}</text>", name)), "{}", name);
+        }
+    }
+}
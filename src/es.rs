@@ -0,0 +1,319 @@
+use nalgebra::{DMatrix, DVector};
+use r::{rngs::StdRng, Rng, SeedableRng};
+use rand_distr::StandardNormal;
+extern crate rand as r;
+
+// Starting step sizes. CMA-ES adapts its own, OpenAI-ES keeps it fixed.
+const CMA_SIGMA: f64 = 0.5;
+const NES_SIGMA: f32 = 0.5;
+const NES_LEARNING_RATE: f32 = 0.2;
+
+// What turns one generation's fitness into the next generation's genomes
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Optimizer {
+    // Selection, crossover and mutation in Population::next_gen
+    #[default]
+    Genetic,
+    Cma,
+    OpenAi,
+}
+
+impl Optimizer {
+    pub const ALL: [Optimizer; 3] = [Optimizer::Genetic, Optimizer::Cma, Optimizer::OpenAi];
+    pub const NAMES: [&'static str; 3] = ["Genetic", "CMA-ES", "OpenAI-ES"];
+}
+
+// A search distribution over flattened weights. `ask` draws a generation and
+// `tell` takes its fitness in the same order.
+pub enum Strategy {
+    Cma(Box<Cma>),
+    OpenAi(Box<OpenAi>),
+}
+
+impl Strategy {
+    // Needs a `lambda` of at least 2 to rank anything
+    pub fn new(optimizer: Optimizer, mean: Vec<f32>, lambda: usize) -> Option<Self> {
+        match optimizer {
+            Optimizer::Genetic => None,
+            Optimizer::Cma => Some(Strategy::Cma(Box::new(Cma::new(mean, lambda)))),
+            Optimizer::OpenAi => Some(Strategy::OpenAi(Box::new(OpenAi::new(mean, lambda)))),
+        }
+    }
+
+    pub fn ask(&mut self) -> Vec<Vec<f32>> {
+        match self {
+            Strategy::Cma(s) => s.ask(),
+            Strategy::OpenAi(s) => s.ask(),
+        }
+    }
+
+    pub fn tell(&mut self, fitness: &[f32]) {
+        match self {
+            Strategy::Cma(s) => s.tell(fitness),
+            Strategy::OpenAi(s) => s.tell(fitness),
+        }
+    }
+}
+
+fn normal(rng: &mut StdRng, n: usize) -> DVector<f64> {
+    DVector::from_fn(n, |_, _| rng.sample::<f64, StandardNormal>(StandardNormal))
+}
+
+// Indices from the fittest down
+fn ranking(fitness: &[f32]) -> Vec<usize> {
+    let mut order = (0..fitness.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| fitness[b].total_cmp(&fitness[a]));
+    order
+}
+
+// Covariance matrix adaptation, following Hansen's "The CMA Evolution
+// Strategy: A Tutorial"
+pub struct Cma {
+    lambda: usize,
+    weights: Vec<f64>,
+    mueff: f64,
+    cc: f64,
+    cs: f64,
+    c1: f64,
+    cmu: f64,
+    damps: f64,
+    chi_n: f64,
+    mean: DVector<f64>,
+    sigma: f64,
+    pc: DVector<f64>,
+    ps: DVector<f64>,
+    c: DMatrix<f64>,
+    b: DMatrix<f64>,
+    d: DVector<f64>,
+    inv_sqrt_c: DMatrix<f64>,
+    evals: usize,
+    eigen_evals: usize,
+    samples: Vec<DVector<f64>>,
+    rng: StdRng,
+}
+
+impl Cma {
+    pub fn new(mean: Vec<f32>, lambda: usize) -> Self {
+        let n = mean.len() as f64;
+        let mu = lambda / 2;
+        let mut weights = (0..mu)
+            .map(|i| (mu as f64 + 0.5).ln() - (i as f64 + 1.).ln())
+            .collect::<Vec<_>>();
+        let sum = weights.iter().sum::<f64>();
+        weights.iter_mut().for_each(|w| *w /= sum);
+        let mueff = 1. / weights.iter().map(|w| w * w).sum::<f64>();
+        let cs = (mueff + 2.) / (n + mueff + 5.);
+        let c1 = 2. / ((n + 1.3).powi(2) + mueff);
+        let dim = mean.len();
+        Self {
+            lambda,
+            weights,
+            mueff,
+            cc: (4. + mueff / n) / (n + 4. + 2. * mueff / n),
+            cs,
+            c1,
+            cmu: (1. - c1).min(2. * (mueff - 2. + 1. / mueff) / ((n + 2.).powi(2) + mueff)),
+            damps: 1. + 2. * (((mueff - 1.) / (n + 1.)).sqrt() - 1.).max(0.) + cs,
+            chi_n: n.sqrt() * (1. - 1. / (4. * n) + 1. / (21. * n * n)),
+            mean: DVector::from_iterator(dim, mean.iter().map(|&x| x as f64)),
+            sigma: CMA_SIGMA,
+            pc: DVector::zeros(dim),
+            ps: DVector::zeros(dim),
+            c: DMatrix::identity(dim, dim),
+            b: DMatrix::identity(dim, dim),
+            d: DVector::from_element(dim, 1.),
+            inv_sqrt_c: DMatrix::identity(dim, dim),
+            evals: 0,
+            eigen_evals: 0,
+            samples: Vec::new(),
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    fn ask(&mut self) -> Vec<Vec<f32>> {
+        let n = self.mean.len();
+        self.samples = (0..self.lambda)
+            .map(|_| {
+                &self.mean
+                    + self.sigma * (&self.b * normal(&mut self.rng, n).component_mul(&self.d))
+            })
+            .collect();
+        self.samples
+            .iter()
+            .map(|x| x.iter().map(|&v| v as f32).collect())
+            .collect()
+    }
+
+    fn tell(&mut self, fitness: &[f32]) {
+        let n = self.mean.len() as f64;
+        self.evals += self.lambda;
+        let order = ranking(fitness);
+        let old = self.mean.clone();
+        self.mean = order
+            .iter()
+            .zip(&self.weights)
+            .fold(DVector::zeros(old.len()), |acc, (&i, &w)| {
+                acc + w * &self.samples[i]
+            });
+        let step = (&self.mean - &old) / self.sigma;
+
+        self.ps = (1. - self.cs) * &self.ps
+            + (self.cs * (2. - self.cs) * self.mueff).sqrt() * (&self.inv_sqrt_c * &step);
+        let hsig = self.ps.norm()
+            / (1. - (1. - self.cs).powf(2. * self.evals as f64 / self.lambda as f64)).sqrt()
+            / self.chi_n
+            < 1.4 + 2. / (n + 1.);
+        let hsig = if hsig { 1. } else { 0. };
+        self.pc = (1. - self.cc) * &self.pc
+            + hsig * (self.cc * (2. - self.cc) * self.mueff).sqrt() * &step;
+
+        let rank_mu = order.iter().zip(&self.weights).fold(
+            DMatrix::zeros(old.len(), old.len()),
+            |acc, (&i, &w)| {
+                let y = (&self.samples[i] - &old) / self.sigma;
+                acc + w * &y * y.transpose()
+            },
+        );
+        self.c = (1. - self.c1 - self.cmu) * &self.c
+            + self.c1
+                * (&self.pc * self.pc.transpose()
+                    + (1. - hsig) * self.cc * (2. - self.cc) * &self.c)
+            + self.cmu * rank_mu;
+        self.sigma *= ((self.cs / self.damps) * (self.ps.norm() / self.chi_n - 1.)).exp();
+
+        // The decomposition is the expensive part, so it lags a little behind
+        if (self.evals - self.eigen_evals) as f64
+            > self.lambda as f64 / (self.c1 + self.cmu) / n / 10.
+        {
+            self.eigen_evals = self.evals;
+            self.c = (&self.c + self.c.transpose()) * 0.5;
+            let eigen = self.c.clone().symmetric_eigen();
+            self.d = eigen.eigenvalues.map(|x| x.max(1e-20).sqrt());
+            self.b = eigen.eigenvectors;
+            self.inv_sqrt_c =
+                &self.b * DMatrix::from_diagonal(&self.d.map(|x| 1. / x)) * self.b.transpose();
+        }
+    }
+}
+
+// Natural evolution strategy as in Salimans et al., "Evolution Strategies as
+// a Scalable Alternative to Reinforcement Learning": mirrored noise pairs and
+// fitness replaced by centered ranks
+pub struct OpenAi {
+    lambda: usize,
+    mean: Vec<f32>,
+    noise: Vec<Vec<f32>>,
+    rng: StdRng,
+}
+
+impl OpenAi {
+    pub fn new(mean: Vec<f32>, lambda: usize) -> Self {
+        Self {
+            lambda,
+            mean,
+            noise: Vec::new(),
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    // Sample 2k is mean + sigma * noise[k] and sample 2k + 1 is mean - sigma * noise[k]
+    fn ask(&mut self) -> Vec<Vec<f32>> {
+        let n = self.mean.len();
+        self.noise = (0..self.lambda.div_ceil(2))
+            .map(|_| normal(&mut self.rng, n).iter().map(|&x| x as f32).collect())
+            .collect();
+        let mut samples = Vec::new();
+        for eps in &self.noise {
+            for sign in [1., -1.] {
+                samples.push(
+                    self.mean
+                        .iter()
+                        .zip(eps)
+                        .map(|(m, e)| m + sign * NES_SIGMA * e)
+                        .collect(),
+                );
+            }
+        }
+        samples.truncate(self.lambda);
+        samples
+    }
+
+    fn tell(&mut self, fitness: &[f32]) {
+        let order = ranking(fitness);
+        // From 0.5 for the fittest down to -0.5
+        let mut utility = vec![0.; fitness.len()];
+        for (rank, &i) in order.iter().enumerate() {
+            utility[i] = 0.5 - rank as f32 / (fitness.len() - 1) as f32;
+        }
+        let scale = NES_LEARNING_RATE / (fitness.len() as f32 * NES_SIGMA);
+        for (i, u) in utility.iter().enumerate() {
+            let sign = if i % 2 == 0 { 1. } else { -1. };
+            for (m, e) in self.mean.iter_mut().zip(&self.noise[i / 2]) {
+                *m += scale * u * sign * e;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sphere(x: &[f32]) -> f32 {
+        x.iter().map(|v| v * v).sum()
+    }
+
+    // Mean distance to the optimum before and after some generations
+    fn descend(strategy: &mut Strategy, gens: usize) -> (f32, f32) {
+        let mean = |s: &Strategy| match s {
+            Strategy::Cma(s) => s.mean.iter().map(|&v| v as f32).collect::<Vec<_>>(),
+            Strategy::OpenAi(s) => s.mean.clone(),
+        };
+        let before = sphere(&mean(strategy));
+        for _ in 0..gens {
+            let samples = strategy.ask();
+            let fitness = samples.iter().map(|x| -sphere(x)).collect::<Vec<_>>();
+            strategy.tell(&fitness);
+        }
+        (before, sphere(&mean(strategy)))
+    }
+
+    #[test]
+    fn ranks_fittest_first() {
+        assert_eq!(ranking(&[0.5, 2., -1., 1.]), [1, 3, 0, 2]);
+    }
+
+    #[test]
+    fn cma_descends_a_sphere() {
+        let mut cma = Cma::new(vec![3.; 5], 10);
+        cma.rng = StdRng::seed_from_u64(1);
+        let (before, after) = descend(&mut Strategy::Cma(Box::new(cma)), 40);
+        assert!(after < before / 100., "{} -> {}", before, after);
+    }
+
+    #[test]
+    fn openai_descends_a_sphere() {
+        let mut nes = OpenAi::new(vec![3.; 5], 20);
+        nes.rng = StdRng::seed_from_u64(1);
+        let (before, after) = descend(&mut Strategy::OpenAi(Box::new(nes)), 100);
+        assert!(after < before / 4., "{} -> {}", before, after);
+    }
+
+    #[test]
+    fn openai_steps_toward_the_fitter_mirror() {
+        let mean = [1f32, -2.];
+        let mut nes = OpenAi::new(mean.to_vec(), 2);
+        nes.rng = StdRng::seed_from_u64(2);
+        let samples = nes.ask();
+        assert_eq!(samples.len(), 2);
+        for k in 0..2 {
+            assert!((samples[0][k] + samples[1][k] - 2. * mean[k]).abs() < 1e-6);
+        }
+        // Utilities of 0.5 and -0.5 both push along the first sample's noise
+        nes.tell(&[1., 0.]);
+        for k in 0..2 {
+            let step = NES_LEARNING_RATE / (2. * NES_SIGMA * NES_SIGMA) * (samples[0][k] - mean[k]);
+            assert!((nes.mean[k] - mean[k] - step).abs() < 1e-5);
+        }
+    }
+}
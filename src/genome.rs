@@ -0,0 +1,96 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{brain::Brain, nn::NN, player::INPUTS};
+
+// Roughly the range each input takes in play, see Player::sense
+const INPUT_RANGES: [(f32, f32); INPUTS] = [
+    (0., 1.3),
+    (-std::f32::consts::PI, std::f32::consts::PI),
+    (-2., 2.),
+    (-2., 2.),
+    (0., 1.),
+];
+
+pub fn euclidean(a: &[f32], b: &[f32]) -> f32 {
+    assert_eq!(a.len(), b.len(), "Genomes not the same length.");
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y) * (x - y))
+        .sum::<f32>()
+        .sqrt()
+}
+
+// 0 for genomes pointing the same way, up to 2 for opposite ones
+pub fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    assert_eq!(a.len(), b.len(), "Genomes not the same length.");
+    let dot = a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0. {
+        // A zero genome has no direction to compare
+        1.
+    } else {
+        1. - dot / norms
+    }
+}
+
+// The same observations for a given seed, so distances can be compared
+pub fn probes(n: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..n)
+        .map(|_| {
+            INPUT_RANGES
+                .iter()
+                .map(|&(lo, hi)| rng.gen_range(lo..hi))
+                .collect()
+        })
+        .collect()
+}
+
+// Mean Euclidean distance between the two networks' outputs. Works across
+// different shapes, unlike the weight-space distances. Recurrent networks see
+// the probes in order, starting from a blank state.
+pub fn behavioral(a: &NN, b: &NN, probes: &[Vec<f32>]) -> f32 {
+    let (mut a, mut b) = (a.clone(), b.clone());
+    a.reset_state();
+    b.reset_state();
+    let n = *a.config.last().unwrap();
+    let (mut ya, mut yb) = (vec![0.; n], vec![0.; n]);
+    let total = probes
+        .iter()
+        .map(|x| {
+            a.feed_forward_into(x, &mut ya);
+            b.feed_forward_into(x, &mut yb);
+            euclidean(&ya, &yb)
+        })
+        .sum::<f32>();
+    total / probes.len().max(1) as f32
+}
+
+// genetic --distance A B prints how far apart two saved networks are
+pub fn compare(args: &[String]) {
+    if args.len() != 2 {
+        eprintln!("Usage: genetic --distance MODEL MODEL");
+        std::process::exit(2);
+    }
+    let load = |path: &String| match Brain::import(path) {
+        Ok(Brain::NN(nn)) => nn,
+        Ok(Brain::Neat(_)) => {
+            eprintln!("{}: NEAT genomes can't be compared", path);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+    let (a, b) = (load(&args[0]), load(&args[1]));
+    if a.config == b.config && a.recurrent.len() == b.recurrent.len() {
+        let (ga, gb) = (a.genome(), b.genome());
+        println!("Euclidean: {:.4}", euclidean(&ga, &gb));
+        println!("Cosine: {:.4}", cosine(&ga, &gb));
+    } else {
+        println!("Different shapes, so only behavior can be compared");
+    }
+    println!("Behavioral: {:.4}", behavioral(&a, &b, &probes(1000, 0)));
+}
@@ -0,0 +1,357 @@
+use std::time::Instant;
+
+use macroquad::rand;
+
+use crate::{
+    brain::{Arch, Brain},
+    cloning::{self, Session},
+    compress::{self, Step},
+    es::Optimizer,
+    model::{Metadata, Model},
+    nn::{ActivationFunc, WeightInit},
+    player::Player,
+    population::Population,
+    schedule::{MutationSchedule, Schedule},
+    stop::StopConditions,
+};
+
+const USAGE: &str = "Usage: genetic --headless [options]
+    --size N              population size (default 100)
+    --hidden A,B,C        hidden layer sizes (default 6,6)
+    --recurrent           feed hidden layers their previous activations
+    --neat                evolve the network topology with NEAT instead
+    --mut-rate R          mutation rate (default 0.05, or the first seed model's
+                          with --seed-dir)
+    --sigma S             std dev of mutated weights (default 1, or the first seed
+                          model's with --seed-dir)
+    --schedule NAME       constant, linear, exp, cosine or plateau (default constant)
+    --schedule-gens N     generations the schedule works over (default 200)
+    --activ NAME          hidden activation: relu, sigmoid, tanh, leakyrelu, elu,
+                          softsign, gaussian, sine, step, identity or swish (default tanh)
+    --output-activ NAME   output activation (default sigmoid)
+    --init NAME           weight init: he, xavier-uniform, xavier-normal, uniform,
+                          zeros or orthogonal (default he)
+    --init-range A        uniform init draws from [-A, A] (default 1)
+    --seed-dir DIR        start from every model in DIR
+    --pad-random          pad a seeded population with random networks
+    --clone PATH          start from a network fitted to a recorded human session,
+                          with a sigmoid output layer
+    --clone-epochs N      passes over the session when fitting it (default 100)
+    --steady              replace each ship as soon as it dies
+    --optimizer NAME      genetic, cma or openai; the evolution strategies work on
+                          dense and recurrent networks only (default genetic)
+    --target-fitness F    stop once a genome reaches fitness F
+    --max-gens N          stop after N generations
+    --max-time SECS       stop after SECS seconds
+    --max-frames N        stop after N simulated frames
+                          (default --max-gens 100 when none of these is given)
+    --prune T             zero weights smaller than T in the best model
+    --prune-impact N      zero the N weights whose removal costs the least fitness
+    --quantize            round the best model's weights to int8 levels, stored
+                          as int8 when saved as .bin
+    --episodes N          episodes to measure fitness over when compressing
+                          (default 5)
+    --out PATH            where to save the best model, in the format its extension
+                          names: .json, .bin, .onnx, .npz, .rs, .h, .dot or .svg
+                          (default best.json)
+    --notes TEXT          notes stored with the saved model";
+
+const DEFAULT_GENS: i32 = 100;
+
+struct Options {
+    size: usize,
+    hlayers: Vec<usize>,
+    arch: Arch,
+    mut_rate: Option<f32>,
+    sigma: Option<f32>,
+    schedule: usize,
+    schedule_gens: i32,
+    activ: ActivationFunc,
+    output_activ: ActivationFunc,
+    init: usize,
+    init_range: f32,
+    seed_dir: Option<String>,
+    pad_random: bool,
+    clone: Option<String>,
+    clone_epochs: usize,
+    steady: bool,
+    optimizer: Optimizer,
+    stop: StopConditions,
+    prune: Option<f32>,
+    prune_impact: Option<usize>,
+    quantize: bool,
+    episodes: u32,
+    out: String,
+    notes: String,
+}
+
+fn parse<T: std::str::FromStr>(flag: &str, value: Option<&String>) -> Result<T, String> {
+    let value = value.ok_or(format!("Missing value for {}", flag))?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
+
+fn parse_activ(flag: &str, value: Option<&String>) -> Result<ActivationFunc, String> {
+    match parse::<String>(flag, value)?.to_lowercase().as_str() {
+        "relu" => Ok(ActivationFunc::ReLU),
+        "sigmoid" => Ok(ActivationFunc::Sigmoid),
+        "tanh" => Ok(ActivationFunc::Tanh),
+        "leakyrelu" => Ok(ActivationFunc::LeakyReLU),
+        "elu" => Ok(ActivationFunc::Elu),
+        "softsign" => Ok(ActivationFunc::Softsign),
+        "gaussian" => Ok(ActivationFunc::Gaussian),
+        "sine" => Ok(ActivationFunc::Sine),
+        "step" => Ok(ActivationFunc::Step),
+        "identity" => Ok(ActivationFunc::Identity),
+        "swish" => Ok(ActivationFunc::Swish),
+        x => Err(format!("Unknown activation function: {}", x)),
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut opts = Options {
+        size: 100,
+        hlayers: vec![6, 6],
+        arch: Arch::Dense,
+        mut_rate: None,
+        sigma: None,
+        schedule: 0,
+        schedule_gens: 200,
+        activ: ActivationFunc::Tanh,
+        output_activ: ActivationFunc::Sigmoid,
+        init: 0,
+        init_range: 1.,
+        seed_dir: None,
+        pad_random: false,
+        clone: None,
+        clone_epochs: cloning::EPOCHS,
+        steady: false,
+        optimizer: Optimizer::Genetic,
+        stop: StopConditions::default(),
+        prune: None,
+        prune_impact: None,
+        quantize: false,
+        episodes: 5,
+        out: "best.json".to_string(),
+        notes: String::new(),
+    };
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--headless" => {}
+            "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            "--size" => opts.size = parse(flag, args.next())?,
+            "--hidden" => {
+                opts.hlayers = args
+                    .next()
+                    .ok_or(format!("Missing value for {}", flag))?
+                    .split(',')
+                    .map(|x| parse(flag, Some(&x.to_string())))
+                    .collect::<Result<_, _>>()?
+            }
+            "--recurrent" => opts.arch = Arch::Recurrent,
+            "--neat" => opts.arch = Arch::Neat,
+            "--mut-rate" => opts.mut_rate = Some(parse(flag, args.next())?),
+            "--sigma" => opts.sigma = Some(parse(flag, args.next())?),
+            "--schedule" => {
+                let name = parse::<String>(flag, args.next())?.to_lowercase();
+                opts.schedule = Schedule::NAMES
+                    .iter()
+                    .position(|x| x.to_lowercase() == name)
+                    .ok_or(format!("Unknown schedule: {}", name))?
+            }
+            "--schedule-gens" => opts.schedule_gens = parse(flag, args.next())?,
+            "--activ" => opts.activ = parse_activ(flag, args.next())?,
+            "--output-activ" => opts.output_activ = parse_activ(flag, args.next())?,
+            "--init" => {
+                let name = parse::<String>(flag, args.next())?.to_lowercase();
+                opts.init = [
+                    "he",
+                    "xavier-uniform",
+                    "xavier-normal",
+                    "uniform",
+                    "zeros",
+                    "orthogonal",
+                ]
+                .iter()
+                .position(|&x| x == name)
+                .ok_or(format!("Unknown weight init: {}", name))?
+            }
+            "--init-range" => opts.init_range = parse(flag, args.next())?,
+            "--seed-dir" => opts.seed_dir = Some(parse(flag, args.next())?),
+            "--pad-random" => opts.pad_random = true,
+            "--clone" => opts.clone = Some(parse(flag, args.next())?),
+            "--clone-epochs" => opts.clone_epochs = parse(flag, args.next())?,
+            "--steady" => opts.steady = true,
+            "--optimizer" => {
+                let name = parse::<String>(flag, args.next())?.to_lowercase();
+                opts.optimizer = match name.as_str() {
+                    "genetic" => Optimizer::Genetic,
+                    "cma" => Optimizer::Cma,
+                    "openai" => Optimizer::OpenAi,
+                    _ => return Err(format!("Unknown optimizer: {}", name)),
+                }
+            }
+            "--target-fitness" => opts.stop.target_fitness = Some(parse(flag, args.next())?),
+            "--max-gens" => opts.stop.max_gens = Some(parse(flag, args.next())?),
+            "--max-time" => opts.stop.max_time = Some(parse(flag, args.next())?),
+            "--max-frames" => opts.stop.max_frames = Some(parse(flag, args.next())?),
+            "--prune" => opts.prune = Some(parse(flag, args.next())?),
+            "--prune-impact" => opts.prune_impact = Some(parse(flag, args.next())?),
+            "--quantize" => opts.quantize = true,
+            "--episodes" => opts.episodes = parse(flag, args.next())?,
+            "--out" => opts.out = parse(flag, args.next())?,
+            "--notes" => opts.notes = parse(flag, args.next())?,
+            x => return Err(format!("Unknown option: {}", x)),
+        }
+    }
+    if opts.size == 0 {
+        return Err("Population size must be at least 1".to_string());
+    }
+    if opts.clone.is_some() && (opts.seed_dir.is_some() || opts.arch != Arch::Dense) {
+        return Err("--clone can't be combined with --seed-dir, --recurrent or --neat".to_string());
+    }
+    // Without any stop condition training would never end or save
+    if opts.stop == StopConditions::default() {
+        opts.stop.max_gens = Some(DEFAULT_GENS);
+    }
+    Ok(opts)
+}
+
+// Trains without a window until a stop condition fires, then saves the best genome
+pub fn run(args: &[String]) {
+    let opts = match parse_args(args) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let seed = macroquad::miniquad::date::now() as u64;
+    rand::srand(seed);
+
+    let schedule = Schedule::from_index(opts.schedule, opts.schedule_gens);
+    let mut_rate = opts.mut_rate.unwrap_or(0.05);
+    let sigma = opts.sigma.unwrap_or(1.);
+    let activs = opts
+        .hlayers
+        .iter()
+        .map(|_| opts.activ)
+        .chain(Some(opts.output_activ))
+        .collect();
+    let mut pop = if let Some(path) = &opts.clone {
+        let session = match Session::load(path) {
+            Ok(session) => session,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        let mut nn = match Player::new(
+            Some(opts.hlayers),
+            Some(mut_rate),
+            Some(activs),
+            Some(WeightInit::from_index(opts.init, opts.init_range)),
+        )
+        .brain
+        {
+            Some(Brain::NN(nn)) => nn,
+            _ => unreachable!(),
+        };
+        nn.mut_sigma = sigma;
+        println!(
+            "Cloned from {}",
+            cloning::train(&mut nn, &session, opts.clone_epochs)
+        );
+        Population::seeded(
+            opts.size,
+            None,
+            vec![Brain::NN(nn)],
+            opts.pad_random,
+            MutationSchedule::new(schedule, mut_rate, sigma),
+        )
+    } else {
+        match &opts.seed_dir {
+            Some(dir) => match Brain::import_dir(dir) {
+                Ok(brains) => {
+                    let base = MutationSchedule::new(
+                        schedule,
+                        opts.mut_rate.unwrap_or(brains[0].mut_rate()),
+                        opts.sigma.unwrap_or(brains[0].mut_sigma()),
+                    );
+                    Population::seeded(opts.size, None, brains, opts.pad_random, base)
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            },
+            None => Population::new(
+                opts.size,
+                None,
+                opts.hlayers,
+                opts.arch,
+                MutationSchedule::new(schedule, mut_rate, sigma),
+                activs,
+                WeightInit::from_index(opts.init, opts.init_range),
+            ),
+        }
+    };
+
+    pop.set_steady(opts.steady);
+    if let Err(e) = pop.set_optimizer(opts.optimizer) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    let start = Instant::now();
+    let reason = loop {
+        pop.update();
+        pop.elapsed = start.elapsed().as_secs_f64();
+        if let Some(reason) = opts.stop.check(&pop) {
+            break reason;
+        }
+    };
+    println!("Training stopped: {}", reason);
+
+    let mut best = pop.best_brain().clone();
+    let steps = opts
+        .prune
+        .map(Step::Magnitude)
+        .into_iter()
+        .chain(opts.prune_impact.map(Step::Impact))
+        .chain(opts.quantize.then_some(Step::Quantize));
+    for step in steps {
+        let name = match step {
+            Step::Magnitude(_) => "Pruned by magnitude",
+            Step::Impact(_) => "Pruned by impact",
+            Step::Quantize => "Quantized",
+        };
+        match &mut best {
+            Brain::NN(nn) => println!(
+                "{}: {}",
+                name,
+                compress::apply(nn, step, opts.episodes, seed)
+            ),
+            Brain::Neat(_) => {
+                eprintln!("NEAT genomes can't be pruned or quantized");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let meta = Metadata::new(pop.gen, pop.best_fitness(), seed, &opts.notes);
+    if let Err(e) = Model::new(best, meta).save(&opts.out) {
+        eprintln!("Unable to write {}: {}", opts.out, e);
+        std::process::exit(1);
+    }
+    println!(
+        "Saved best model (fitness {:.2}) to {}",
+        pop.best_fitness(),
+        opts.out
+    );
+}
@@ -0,0 +1,109 @@
+use std::collections::{HashSet, VecDeque};
+
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum Operator {
+    Random,
+    Loaded,
+    Elite,
+    Crossover,
+    // Offspring whose two parents were the same genome
+    Mutation,
+    // Drawn from an evolution strategy's distribution
+    Sampled,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Genome {
+    pub id: usize,
+    pub parents: Vec<usize>,
+    pub operator: Operator,
+    pub gen: i32,
+    // Known once the genome's world is over
+    pub fitness: Option<f32>,
+}
+
+// Every genome ever created in a population, indexed by id
+#[derive(Default)]
+pub struct Lineage {
+    genomes: Vec<Genome>,
+}
+
+impl Lineage {
+    pub fn record(&mut self, parents: Vec<usize>, operator: Operator, gen: i32) -> usize {
+        let id = self.genomes.len();
+        self.genomes.push(Genome {
+            id,
+            parents,
+            operator,
+            gen,
+            fitness: None,
+        });
+        id
+    }
+
+    pub fn set_fitness(&mut self, id: usize, fitness: f32) {
+        if let Some(genome) = self.genomes.get_mut(id) {
+            genome.fitness = Some(fitness);
+        }
+    }
+
+    // The genome itself followed by all its ancestors, newest first
+    pub fn ancestry(&self, id: usize) -> Vec<&Genome> {
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::from([id]);
+        let mut ancestors = Vec::new();
+        while let Some(id) = queue.pop_front() {
+            if !seen.insert(id) {
+                continue;
+            }
+            if let Some(genome) = self.genomes.get(id) {
+                queue.extend(&genome.parents);
+                ancestors.push(genome);
+            }
+        }
+        ancestors.sort_by(|a, b| b.gen.cmp(&a.gen).then(b.id.cmp(&a.id)));
+        ancestors
+    }
+
+    pub fn to_json(&self, id: usize) -> String {
+        serde_json::to_string(&self.ancestry(id)).unwrap()
+    }
+
+    pub fn to_dot(&self, id: usize) -> String {
+        let ancestry = self.ancestry(id);
+        let mut dot = String::from("digraph lineage {\n    rankdir=LR;\n    node [shape=box];\n");
+        for genome in &ancestry {
+            dot += &format!(
+                "    g{} [label=\"#{}\\ngen {}\\n{:?}{}\"{}];\n",
+                genome.id,
+                genome.id,
+                genome.gen,
+                genome.operator,
+                genome
+                    .fitness
+                    .map_or(String::new(), |f| format!("\\nfitness {:.2}", f)),
+                if genome.id == id { ", color=green" } else { "" },
+            );
+        }
+        for genome in &ancestry {
+            let mut parents = genome.parents.clone();
+            parents.dedup();
+            for parent in parents {
+                dot += &format!("    g{} -> g{};\n", parent, genome.id);
+            }
+        }
+        dot += "}\n";
+        dot
+    }
+
+    pub fn export(&self, id: usize, path: &str) -> std::io::Result<()> {
+        let out = if path.ends_with(".dot") || path.ends_with(".gv") {
+            self.to_dot(id)
+        } else {
+            self.to_json(id)
+        };
+        std::fs::write(path, out)
+    }
+}
@@ -1,13 +1,35 @@
 #![windows_subsystem = "windows"]
 
 mod asteroids;
+mod binary;
+mod brain;
+mod cloning;
+mod codegen;
+mod compress;
+mod diagram;
+mod es;
+mod genome;
+mod headless;
+mod lineage;
+mod model;
+mod neat;
 mod nn;
+mod npz;
+mod onnx;
 mod player;
 mod population;
+mod schedule;
 mod skins;
+mod stop;
 mod world;
 
-use nn::{ActivationFunc, NN};
+use brain::{Arch, Brain};
+use cloning::Session;
+use compress::Step;
+use es::Optimizer;
+use lineage::Operator;
+use model::{Metadata, Model};
+use nn::{ActivationFunc, Hover, WeightInit};
 use tinyfiledialogs::*;
 
 use macroquad::{
@@ -15,11 +37,48 @@ use macroquad::{
     ui::{hash, root_ui, widgets},
 };
 use population::{AutoSwitch, Population};
+use schedule::{MutationSchedule, Schedule};
+use stop::StopConditions;
 use world::World;
 
 pub const WIDTH: f32 = 800.;
 pub const HEIGHT: f32 = 780.;
 
+fn activ_funcs(activ: &[usize; 4]) -> Vec<ActivationFunc> {
+    activ.iter().map(|&i| ActivationFunc::ALL[i]).collect()
+}
+
+// UI indices for a loaded brain's hidden and output activations
+fn activ_indices(brain: &Brain) -> [usize; 4] {
+    let funcs = brain.activ_funcs();
+    let index = |f: &ActivationFunc| ActivationFunc::ALL.iter().position(|x| x == f).unwrap_or(0);
+    let mut activ = [index(&funcs[0]); 4];
+    for (i, f) in funcs.iter().take(funcs.len() - 1).take(3).enumerate() {
+        activ[i] = index(f);
+    }
+    activ[3] = index(funcs.last().unwrap());
+    activ
+}
+
+// Largest choice in the layer size boxes
+const MAX_WIDTH: usize = 16;
+
+// Networks the UI has layer slots for, so loading one keeps its settings intact
+fn supported(brain: Brain) -> Result<Brain, String> {
+    match &brain {
+        Brain::NN(nn) if nn.hidden_layers().len() > 3 => Err(format!(
+            "This network has {} hidden layers, but the app only supports up to 3. Train it with --headless instead.",
+            nn.hidden_layers().len()
+        )),
+        Brain::NN(nn) if nn.hidden_layers().iter().any(|&n| n > MAX_WIDTH) => Err(format!(
+            "This network has hidden layers of {:?} neurons, but the app only supports up to {}. Train it with --headless instead.",
+            nn.hidden_layers(),
+            MAX_WIDTH
+        )),
+        _ => Ok(brain),
+    }
+}
+
 fn window_conf() -> Conf {
     Conf {
         window_title: "Asteroids".to_string(),
@@ -29,9 +88,21 @@ fn window_conf() -> Conf {
         ..Default::default()
     }
 }
-#[macroquad::main(window_conf)]
-async fn main() {
-    rand::srand(macroquad::miniquad::date::now() as _);
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.iter().any(|a| a == "--headless") {
+        headless::run(&args);
+    } else if args.first().is_some_and(|a| a == "--distance") {
+        genome::compare(&args[1..]);
+    } else {
+        macroquad::Window::from_config(window_conf(), gui());
+    }
+}
+
+async fn gui() {
+    let seed = macroquad::miniquad::date::now() as u64;
+    rand::srand(seed);
 
     let pause = Texture2D::from_file_with_format(include_bytes!("../assets/pause.png"), None);
     let play = Texture2D::from_file_with_format(include_bytes!("../assets/play.png"), None);
@@ -65,31 +136,66 @@ async fn main() {
     let mut speedup = 1;
     let mut paused = false;
     let mut bias = false;
+    // Layer and index of the neuron clicked in the brain panel
+    let mut selected: Option<(usize, usize)> = None;
+    // Hand edits to the tracked ship's network: the weight being dragged and
+    // the pointer height last frame, and snapshots to undo with the world id.
+    // Ids start over with every population, so these are dropped with it.
+    let mut dragging: Option<(usize, usize, usize, f32)> = None;
+    let mut edits: Vec<(usize, Brain)> = Vec::new();
     let mut human = false;
+    // Frames of human play, kept across games until saved
+    let mut session = Session::default();
     let mut size: u32 = 100;
-    let mut world: World = World::new(None, None, None);
+    let mut world: World = World::new(None, None, None, None);
 
     let mut hlayers: Vec<usize> = vec![6, 6, 0];
     let mut prev_hlayers = hlayers.clone();
 
+    // Index into Arch::ALL
+    let mut arch: usize = 0;
+    let mut prev_arch: usize = 0;
+
     let mut mut_rate = 0.05;
     let mut prev_mut_rate = 0.05;
+    let mut mut_sigma = 1.;
+    let mut prev_mut_sigma = 1.;
 
-    let mut activ: usize = 0;
-    let mut prev_activ: usize = 0;
-    let activs = [
-        ActivationFunc::ReLU,
-        ActivationFunc::Sigmoid,
-        ActivationFunc::Tanh,
-    ];
+    let mut sched: usize = 0;
+    let mut sched_gens: u32 = 200;
+    let mut prev_sched = (sched, sched_gens);
+
+    // Indices into ActivationFunc::ALL for the 3 hidden layer slots and the output layer
+    let mut activ: [usize; 4] = [2, 2, 2, 1];
+    let mut prev_activ = activ;
+    // Index into WeightInit::NAMES, and the range for uniform init
+    let mut init: usize = 0;
+    let mut init_range: f32 = 1.;
+    let mut prev_init = (init, init_range);
     let mut auto_switch = Some(AutoSwitch::BestAlive);
+    let mut steady = false;
+    // Index into Optimizer::ALL
+    let mut optimizer: usize = 0;
+
+    // 0 disables a condition
+    let mut target_fitness: f32 = 0.;
+    let mut max_gens: u32 = 0;
+    let mut max_minutes: f32 = 0.;
+    let mut max_kframes: u32 = 0;
+    let mut prev_stop = StopConditions::default();
 
     let mut pop = Population::new(
         size as usize,
         auto_switch,
         hlayers.clone(),
-        mut_rate,
-        activs[activ],
+        Arch::ALL[arch],
+        MutationSchedule::new(
+            Schedule::from_index(sched, sched_gens as i32),
+            mut_rate,
+            mut_sigma,
+        ),
+        activ_funcs(&activ),
+        WeightInit::from_index(init, init_range),
     );
 
     let ui_thick = 34.;
@@ -104,14 +210,61 @@ async fn main() {
     loop {
         clear_background(BLACK);
         set_camera(&gamecam);
+        if pop.steady != steady {
+            pop.set_steady(steady);
+        }
+        // Also puts the choice back after the population is restarted
+        if pop.optimizer != Optimizer::ALL[optimizer] {
+            if let Err(e) = pop.set_optimizer(Optimizer::ALL[optimizer]) {
+                message_box_ok("Optimizer", &e, MessageBoxIcon::Error);
+                optimizer = 0;
+            }
+            edits.clear();
+            dragging = None;
+            selected = None;
+        }
         if !paused {
             for _ in 0..speedup {
                 if !human {
                     pop.update()
-                } else if !world.over {
-                    world.update()
+                } else if !world.over && world.update() {
+                    session.record(&world.player);
                 };
             }
+            if !human {
+                pop.elapsed += get_frame_time() as f64;
+            }
+        }
+        let stop = StopConditions {
+            target_fitness: (target_fitness > 0.).then_some(target_fitness),
+            max_gens: (max_gens > 0).then_some(max_gens as i32),
+            max_time: (max_minutes > 0.).then_some(max_minutes as f64 * 60.),
+            max_frames: (max_kframes > 0).then_some(max_kframes as u64 * 1000),
+        };
+        // New limits are checked afresh once training resumes
+        if stop != prev_stop {
+            pop.stopped = None;
+            prev_stop = stop;
+        }
+        if !human && !paused && pop.stopped.is_none() {
+            pop.stopped = stop.check(&pop);
+            if let Some(reason) = pop.stopped {
+                paused = true;
+                if message_box_yes_no(
+                    "Training Stopped",
+                    &format!("Training {}. Save the best model?", reason),
+                    MessageBoxIcon::Info,
+                    YesNo::Yes,
+                ) == YesNo::Yes
+                {
+                    if let Some(path) = save_file_dialog("Save Model", "model.json") {
+                        let meta = Metadata::new(pop.gen, pop.best_fitness(), seed, "");
+                        if let Err(e) = Model::new(pop.best_brain().clone(), meta).save(&path) {
+                            message_box_ok("Save Model", &e.to_string(), MessageBoxIcon::Error);
+                        }
+                    }
+                }
+            }
         }
         if human {
             world.draw(pop.debug);
@@ -138,11 +291,38 @@ async fn main() {
         );
 
         set_camera(&netcam);
-        pop.worlds[pop.track].player.draw_brain(
+        let (net_w, net_h) = (
             screen_width() - WIDTH - 3. * th,
             (screen_height() - 3. * th) * 0.5,
-            bias,
         );
+        let mouse: (f32, f32) = netcam.screen_to_world(mouse_position().into()).into();
+        // Edits only apply to the ship they were made on
+        let id = pop.worlds[pop.track].id;
+        edits.retain(|(e, _)| *e == id);
+        let player = &mut pop.worlds[pop.track].player;
+        if is_mouse_button_pressed(MouseButton::Left)
+            && mouse.0.abs() < net_w * 0.5
+            && mouse.1.abs() < net_h * 0.5
+        {
+            selected = None;
+            match player.brain_hover(net_w, net_h, bias, mouse) {
+                Some(Hover::Neuron(i, j)) => selected = Some((i, j)),
+                Some(Hover::Weight(i, j, k)) => {
+                    edits.push((id, player.brain.clone().unwrap()));
+                    dragging = Some((i, j, k, mouse.1));
+                }
+                None => {}
+            }
+        }
+        if !is_mouse_button_down(MouseButton::Left) || edits.is_empty() {
+            dragging = None;
+        }
+        if let (Some((i, j, k, y)), Some(Brain::NN(nn))) = (&mut dragging, &mut player.brain) {
+            // Dragging up makes the weight more positive
+            nn.weights[*i][(*j, *k)] += (*y - mouse.1) * 0.01;
+            *y = mouse.1;
+        }
+        player.draw_brain(net_w, net_h, bias, mouse, selected);
         set_camera(&statcam);
         let w = if human {
             &world
@@ -185,37 +365,80 @@ async fn main() {
                         ui.same_line(242.);
                         if widgets::Button::new("Load Model").ui(ui) {
                             if let Some(path) = open_file_dialog("Load Model", "model.json", None) {
-                                let brain = NN::import(&path);
-                                size = 1;
-                                hlayers = brain
-                                    .config
-                                    .iter()
-                                    .take(brain.config.len() - 1)
-                                    .skip(1)
-                                    .map(|x| x - 1)
-                                    .collect::<Vec<_>>();
-                                hlayers.resize(3, 0);
-                                mut_rate = brain.mut_rate;
-                                activ = activs.iter().position(|&x| x == brain.activ_func).unwrap();
-
-                                prev_hlayers = hlayers.clone();
-                                prev_mut_rate = mut_rate;
-                                prev_activ = activ;
+                                match Brain::import(&path)
+                                    .map_err(|e| e.to_string())
+                                    .and_then(supported)
+                                {
+                                    Ok(brain) => {
+                                        size = 1;
+                                        if let Brain::NN(nn) = &brain {
+                                            hlayers = nn.hidden_layers();
+                                            hlayers.resize(3, 0);
+                                            init = nn.init.index();
+                                            if let WeightInit::Uniform(a) = nn.init {
+                                                init_range = a;
+                                            }
+                                        }
+                                        arch = Arch::ALL
+                                            .iter()
+                                            .position(|&a| a == brain.arch())
+                                            .unwrap();
+                                        mut_rate = brain.mut_rate();
+                                        mut_sigma = brain.mut_sigma();
+                                        activ = activ_indices(&brain);
 
-                                pop = Population::new(
-                                    size as usize,
-                                    auto_switch,
-                                    hlayers.clone(),
-                                    mut_rate,
-                                    activs[activ],
-                                );
-                                pop.worlds[0] = World::simulate(brain);
+                                        prev_hlayers = hlayers.clone();
+                                        prev_arch = arch;
+                                        prev_mut_rate = mut_rate;
+                                        prev_mut_sigma = mut_sigma;
+                                        prev_activ = activ;
+                                        prev_init = (init, init_range);
+
+                                        pop = Population::new(
+                                            size as usize,
+                                            auto_switch,
+                                            hlayers.clone(),
+                                            Arch::ALL[arch],
+                                            MutationSchedule::new(
+                                                Schedule::from_index(sched, sched_gens as i32),
+                                                mut_rate,
+                                                mut_sigma,
+                                            ),
+                                            activ_funcs(&activ),
+                                            WeightInit::from_index(init, init_range),
+                                        );
+                                        edits.clear();
+                                        dragging = None;
+                                        selected = None;
+                                        pop.worlds[0] = World::simulate(brain);
+                                        pop.worlds[0].id =
+                                            pop.lineage.record(vec![], Operator::Loaded, 0);
+                                    }
+                                    Err(e) => {
+                                        message_box_ok("Load Model", &e, MessageBoxIcon::Error)
+                                    }
+                                }
                             }
                         }
                         ui.same_line(0.);
                         if widgets::Button::new("Save Model").ui(ui) {
                             if let Some(path) = save_file_dialog("Save Model", "model.json") {
-                                pop.worlds[pop.track].export_brain(&path);
+                                let edited = if edits.is_empty() {
+                                    ""
+                                } else {
+                                    "edited by hand"
+                                };
+                                let notes = input_box("Save Model", "Notes (optional):", edited)
+                                    .unwrap_or_default();
+                                let world = &pop.worlds[pop.track];
+                                let meta = Metadata::new(pop.gen, world.fitness, seed, &notes);
+                                if let Err(e) = world.export_brain(&path, meta) {
+                                    message_box_ok(
+                                        "Save Model",
+                                        &e.to_string(),
+                                        MessageBoxIcon::Error,
+                                    );
+                                }
                             }
                         }
                         ui.same_line(0.);
@@ -235,6 +458,9 @@ async fn main() {
                             || is_key_pressed(KeyCode::P)
                         {
                             paused = !paused;
+                            if !paused {
+                                pop.stopped = None;
+                            }
                         };
                     });
                 widgets::Group::new(hash!(), vec2(ui_width, ui_thick))
@@ -272,15 +498,24 @@ async fn main() {
                         ui.same_line(0.);
                         if widgets::Button::new(restart).ui(ui) || is_key_pressed(KeyCode::R) {
                             if human {
-                                world = World::new(None, None, None);
+                                world = World::new(None, None, None, None);
                             } else {
                                 pop = Population::new(
                                     size as usize,
                                     auto_switch,
                                     hlayers.clone(),
-                                    mut_rate,
-                                    activs[activ],
+                                    Arch::ALL[arch],
+                                    MutationSchedule::new(
+                                        Schedule::from_index(sched, sched_gens as i32),
+                                        mut_rate,
+                                        mut_sigma,
+                                    ),
+                                    activ_funcs(&activ),
+                                    WeightInit::from_index(init, init_range),
                                 );
+                                edits.clear();
+                                dragging = None;
+                                selected = None;
                             }
                         };
                     });
@@ -308,6 +543,78 @@ async fn main() {
                         auto_switch = None;
                         pop.auto_switch = auto_switch;
                     }
+                    if ui.button(None, "Save Lineage") {
+                        if let Some(path) = save_file_dialog("Save Lineage", "lineage.dot") {
+                            if let Err(e) = pop.lineage.export(pop.worlds[pop.track].id, &path) {
+                                message_box_ok("Save Lineage", &e.to_string(), MessageBoxIcon::Error);
+                            }
+                        }
+                    }
+                    // Click a neuron in the panel first; drag a connection to
+                    // change its weight
+                    let zero = match selected {
+                        Some((0, _)) => "Disable Input",
+                        _ => "Zero Neuron",
+                    };
+                    if ui.button(None, zero) || is_key_pressed(KeyCode::Delete) {
+                        let world = &mut pop.worlds[pop.track];
+                        if let (Some((i, j)), Some(Brain::NN(nn))) =
+                            (selected, &mut world.player.brain)
+                        {
+                            if i < nn.config.len() && j < nn.config[i] {
+                                edits.push((world.id, Brain::NN(nn.clone())));
+                                nn.zero_neuron(i, j);
+                            }
+                        }
+                    }
+                    if ui.button(None, format!("Undo Edit ({})", edits.len()).as_str())
+                        || is_key_pressed(KeyCode::U)
+                    {
+                        if let Some((_, brain)) = edits.pop() {
+                            pop.worlds[pop.track].player.brain = Some(brain);
+                        }
+                    }
+                    let mut step = None;
+                    if ui.button(None, "Prune Weights") {
+                        step = input_box("Prune Weights", "Zero weights smaller than:", "0.1")
+                            .and_then(|s| s.parse().ok())
+                            .map(Step::Magnitude);
+                    }
+                    if ui.button(None, "Prune By Impact") {
+                        step = input_box("Prune By Impact", "Number of weights to zero:", "10")
+                            .and_then(|s| s.parse().ok())
+                            .map(Step::Impact);
+                    }
+                    if ui.button(None, "Quantize Int8") {
+                        step = Some(Step::Quantize);
+                    }
+                    let world = &mut pop.worlds[pop.track];
+                    if let (Some(step), Some(Brain::NN(nn))) = (step, &mut world.player.brain) {
+                        edits.push((world.id, Brain::NN(nn.clone())));
+                        // Pruning by impact plays every episode once per weight
+                        // it tries, so it gets one here to keep the window responsive
+                        let episodes = if matches!(step, Step::Impact(_)) { 1 } else { 5 };
+                        let report = compress::apply(nn, step, episodes, seed);
+                        if message_box_yes_no(
+                            "Compress Model",
+                            &format!("{}. Save the compressed model?", report),
+                            MessageBoxIcon::Question,
+                            YesNo::No,
+                        ) == YesNo::Yes
+                        {
+                            if let Some(path) = save_file_dialog("Save Model", "model.bin") {
+                                let meta = Metadata::new(pop.gen, report.after, seed, "compressed");
+                                if let Err(e) = Model::new(Brain::NN(nn.clone()), meta).save(&path)
+                                {
+                                    message_box_ok(
+                                        "Save Model",
+                                        &e.to_string(),
+                                        MessageBoxIcon::Error,
+                                    );
+                                }
+                            }
+                        }
+                    }
                     ui.label(None, " ");
                     ui.label(None, "Auto Switch");
                     ui.label(None, "When Dead to:");
@@ -361,46 +668,247 @@ async fn main() {
                     ) {
                         human = !human;
                         if human {
-                            world = World::new(None, None, None);
+                            world = World::new(None, None, None, None);
                         } else {
                             pop = Population::new(
                                 size as usize,
                                 auto_switch,
                                 hlayers.clone(),
-                                mut_rate,
-                                activs[activ],
+                                Arch::ALL[arch],
+                                MutationSchedule::new(
+                                    Schedule::from_index(sched, sched_gens as i32),
+                                    mut_rate,
+                                    mut_sigma,
+                                ),
+                                activ_funcs(&activ),
+                                WeightInit::from_index(init, init_range),
                             );
+                            edits.clear();
+                            dragging = None;
+                            selected = None;
                         }
                     }
                     ui.pop_skin();
+                    if human {
+                        if ui.button(
+                            None,
+                            format!("Save Session ({})", session.samples.len()).as_str(),
+                        ) && !session.samples.is_empty()
+                        {
+                            if let Some(path) = save_file_dialog("Save Session", "session.json") {
+                                match session.save(&path) {
+                                    Ok(()) => session = Session::default(),
+                                    Err(e) => message_box_ok(
+                                        "Save Session",
+                                        &e.to_string(),
+                                        MessageBoxIcon::Error,
+                                    ),
+                                }
+                            }
+                        }
+                    } else if ui.button(None, "Clone From Session") {
+                        if let Some(path) =
+                            open_file_dialog("Clone From Session", "session.json", None)
+                        {
+                            match Session::load(&path) {
+                                Ok(recorded) => {
+                                    // Always a dense network ending in a sigmoid
+                                    arch = 0;
+                                    activ[3] = 1;
+                                    prev_arch = arch;
+                                    prev_activ = activ;
+                                    let mut nn = match player::Player::new(
+                                        Some(hlayers.clone()),
+                                        Some(mut_rate),
+                                        Some(activ_funcs(&activ)),
+                                        Some(WeightInit::from_index(init, init_range)),
+                                    )
+                                    .brain
+                                    {
+                                        Some(Brain::NN(nn)) => nn,
+                                        _ => unreachable!(),
+                                    };
+                                    nn.mut_sigma = mut_sigma;
+                                    let fit = cloning::train(&mut nn, &recorded, cloning::EPOCHS);
+                                    message_box_ok(
+                                        "Clone From Session",
+                                        &format!("Trained on {}", fit),
+                                        MessageBoxIcon::Info,
+                                    );
+                                    pop = Population::seeded(
+                                        size as usize,
+                                        auto_switch,
+                                        vec![Brain::NN(nn)],
+                                        false,
+                                        MutationSchedule::new(
+                                            Schedule::from_index(sched, sched_gens as i32),
+                                            mut_rate,
+                                            mut_sigma,
+                                        ),
+                                    );
+                                    edits.clear();
+                                    dragging = None;
+                                    selected = None;
+                                }
+                                Err(e) => {
+                                    message_box_ok("Clone From Session", &e, MessageBoxIcon::Error)
+                                }
+                            }
+                        }
+                    }
+                    if ui.button(None, "Seed From Folder") {
+                        if let Some(path) = select_folder_dialog("Seed Population", "models") {
+                            match Brain::import_dir(&path).and_then(|brains| {
+                                brains.into_iter().map(supported).collect::<Result<Vec<_>, _>>()
+                            }) {
+                                Ok(brains) => {
+                                    let pad_random = message_box_yes_no(
+                                        "Seed Population",
+                                        &format!(
+                                            "Loaded {} models. Pad the population with random networks instead of mutated offspring?",
+                                            brains.len()
+                                        ),
+                                        MessageBoxIcon::Question,
+                                        YesNo::No,
+                                    ) == YesNo::Yes;
+                                    size = std::cmp::max(size, brains.len() as u32);
+                                    if let Brain::NN(nn) = &brains[0] {
+                                        hlayers = nn.hidden_layers();
+                                        hlayers.resize(3, 0);
+                                        init = nn.init.index();
+                                        if let WeightInit::Uniform(a) = nn.init {
+                                            init_range = a;
+                                        }
+                                    }
+                                    arch = Arch::ALL
+                                        .iter()
+                                        .position(|&a| a == brains[0].arch())
+                                        .unwrap();
+                                    mut_rate = brains[0].mut_rate();
+                                    mut_sigma = brains[0].mut_sigma();
+                                    activ = activ_indices(&brains[0]);
+
+                                    prev_hlayers = hlayers.clone();
+                                    prev_arch = arch;
+                                    prev_mut_rate = mut_rate;
+                                    prev_mut_sigma = mut_sigma;
+                                    prev_activ = activ;
+                                    prev_init = (init, init_range);
+
+                                    human = false;
+                                    pop = Population::seeded(
+                                        size as usize,
+                                        auto_switch,
+                                        brains,
+                                        pad_random,
+                                        MutationSchedule::new(
+                                            Schedule::from_index(sched, sched_gens as i32),
+                                            mut_rate,
+                                            mut_sigma,
+                                        ),
+                                    );
+                                    edits.clear();
+                                    dragging = None;
+                                    selected = None;
+                                }
+                                Err(e) => {
+                                    message_box_ok("Seed Population", &e, MessageBoxIcon::Error)
+                                }
+                            }
+                        }
+                    }
                     ui.label(None, "Mutation Rate");
                     ui.drag(hash!(), "«Drag»", Some((0., 1.)), &mut mut_rate);
                     if prev_mut_rate != mut_rate {
                         pop.change_mut(mut_rate);
                         prev_mut_rate = mut_rate;
                     }
-                    ui.label(None, "Activation Func");
-                    ui.combo_box(hash!(), "«Select»", &["ReLU", "Sigm", "Tanh"], &mut activ);
+                    ui.label(None, "Mutation Sigma");
+                    ui.drag(hash!(), "«Drag»", Some((0., 4.)), &mut mut_sigma);
+                    if prev_mut_sigma != mut_sigma {
+                        pop.change_sigma(mut_sigma);
+                        prev_mut_sigma = mut_sigma;
+                    }
+                    ui.label(None, "Schedule");
+                    ui.combo_box(hash!(), "«Select»", &Schedule::NAMES, &mut sched);
+                    ui.drag(hash!(), "Gens", Some((1, 5000)), &mut sched_gens);
+                    if prev_sched != (sched, sched_gens) {
+                        pop.change_schedule(Schedule::from_index(sched, sched_gens as i32));
+                        prev_sched = (sched, sched_gens);
+                    }
+                    ui.label(
+                        None,
+                        &format!(
+                            "Now: {:.3} sd {:.2}",
+                            pop.schedule.rate(),
+                            pop.schedule.sigma()
+                        ),
+                    );
+                    ui.label(None, "Activations");
+                    ui.combo_box(hash!(), "Layer 1", &ActivationFunc::NAMES, &mut activ[0]);
+                    ui.combo_box(hash!(), "Layer 2", &ActivationFunc::NAMES, &mut activ[1]);
+                    ui.combo_box(hash!(), "Layer 3", &ActivationFunc::NAMES, &mut activ[2]);
+                    ui.combo_box(hash!(), "Output", &ActivationFunc::NAMES, &mut activ[3]);
                     if prev_activ != activ {
-                        pop.change_activ(activs[activ]);
+                        pop.change_activ(&activ_funcs(&activ));
                         prev_activ = activ;
                     }
+                    ui.label(None, "Evolution");
+                    if ui.button(
+                        None,
+                        if steady {
+                            "Steady-State"
+                        } else {
+                            "Generational"
+                        },
+                    ) {
+                        steady = !steady;
+                    }
+                    ui.combo_box(hash!(), "Optimizer", &Optimizer::NAMES, &mut optimizer);
+                    ui.label(None, " ");
+                    ui.label(None, "Stop Training");
+                    ui.label(None, "(0 = never)");
+                    ui.drag(hash!(), "Fitness", Some((0., 10000.)), &mut target_fitness);
+                    ui.drag(hash!(), "Gens", Some((0, 10000)), &mut max_gens);
+                    ui.drag(hash!(), "Minutes", Some((0., 1440.)), &mut max_minutes);
+                    ui.drag(hash!(), "kFrames", Some((0, 100000)), &mut max_kframes);
                     ui.label(None, " ");
+                    ui.label(None, "Network");
+                    ui.combo_box(hash!(), "«Select»", &Arch::NAMES, &mut arch);
                     ui.label(None, "Hidden Layers");
                     ui.label(None, "Neurons Config");
 
                     ui.combo_box(hash!(), "Layer 1", nums, &mut hlayers[0]);
                     ui.combo_box(hash!(), "Layer 2", nums, &mut hlayers[1]);
                     ui.combo_box(hash!(), "Layer 3", nums, &mut hlayers[2]);
-                    if prev_hlayers != hlayers {
+                    ui.label(None, "Weight Init");
+                    ui.combo_box(hash!(), "«Select»", &WeightInit::NAMES, &mut init);
+                    if init == WeightInit::Uniform(0.).index() {
+                        ui.drag(hash!(), "Range", Some((0., 4.)), &mut init_range);
+                    }
+                    if prev_hlayers != hlayers
+                        || prev_arch != arch
+                        || prev_init != (init, init_range)
+                    {
                         pop = Population::new(
                             size as usize,
                             auto_switch,
                             hlayers.clone(),
-                            mut_rate,
-                            activs[activ],
+                            Arch::ALL[arch],
+                            MutationSchedule::new(
+                                Schedule::from_index(sched, sched_gens as i32),
+                                mut_rate,
+                                mut_sigma,
+                            ),
+                            activ_funcs(&activ),
+                            WeightInit::from_index(init, init_range),
                         );
+                        edits.clear();
+                        dragging = None;
+                        selected = None;
                         prev_hlayers = hlayers.clone();
+                        prev_arch = arch;
+                        prev_init = (init, init_range);
                     }
                 });
                 ui.pop_skin();
@@ -0,0 +1,300 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    binary,
+    brain::{Brain, ImportError},
+    codegen, diagram,
+    neat::Neat,
+    nn::NN,
+    npz, onnx,
+    player::{DRAG, INPUT_NAMES, SHOT_INTERVAL},
+    world::SPAWN_INTERVAL,
+    HEIGHT, WIDTH,
+};
+
+// Bumped whenever the layout of model files changes. Files without a version
+// are bare brains from before the envelope existed.
+pub const VERSION: u32 = 1;
+
+// The game a model was trained in, since changing any of these changes what a
+// network has learned to expect
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GameRules {
+    pub width: f32,
+    pub height: f32,
+    pub drag: f32,
+    pub shot_interval: u32,
+    pub spawn_interval: u32,
+}
+
+impl Default for GameRules {
+    fn default() -> Self {
+        Self {
+            width: WIDTH,
+            height: HEIGHT,
+            drag: DRAG,
+            shot_interval: SHOT_INTERVAL,
+            spawn_interval: SPAWN_INTERVAL,
+        }
+    }
+}
+
+// Unknown fields are None for models migrated from the bare format
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Metadata {
+    pub generation: Option<i32>,
+    pub fitness: Option<f32>,
+    pub seed: Option<u64>,
+    pub rules: Option<GameRules>,
+    #[serde(default)]
+    pub inputs: Vec<String>,
+    // Seconds since the Unix epoch
+    pub timestamp: Option<u64>,
+    #[serde(default)]
+    pub notes: String,
+}
+
+impl Metadata {
+    pub fn new(generation: i32, fitness: f32, seed: u64, notes: &str) -> Self {
+        Self {
+            generation: Some(generation),
+            fitness: Some(fitness),
+            seed: Some(seed),
+            rules: Some(GameRules::default()),
+            inputs: INPUT_NAMES.iter().map(|s| s.to_string()).collect(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|d| d.as_secs()),
+            notes: notes.to_string(),
+        }
+    }
+}
+
+// Tries each kind of brain in turn rather than through the untagged enum, so
+// that a broken dense network reports what is wrong with it instead of serde's
+// "did not match any variant"
+fn brain_from_value(value: Value) -> Result<Brain, serde_json::Error> {
+    match serde_json::from_value::<NN>(value.clone()) {
+        Ok(nn) => Ok(Brain::NN(nn)),
+        Err(e) => serde_json::from_value::<Neat>(value)
+            .map(Brain::Neat)
+            .map_err(|_| e),
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Model {
+    pub version: u32,
+    pub meta: Metadata,
+    pub brain: Brain,
+}
+
+impl Model {
+    pub fn new(brain: Brain, meta: Metadata) -> Self {
+        Self {
+            version: VERSION,
+            meta,
+            brain,
+        }
+    }
+
+    pub fn export(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    // Picks the format from the extension: .bin for the compact binary one,
+    // .onnx to run the network elsewhere, .npz for NumPy, .rs or .h for
+    // source code and .dot, .gv or .svg for a diagram (all without metadata),
+    // anything else JSON
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let invalid = |e: &str| std::io::Error::new(std::io::ErrorKind::InvalidInput, e);
+        if path.ends_with(".bin") {
+            std::fs::write(path, binary::encode(self).map_err(|e| invalid(&e))?)
+        } else if path.ends_with(".npz") {
+            match &self.brain {
+                Brain::NN(nn) => std::fs::write(path, npz::export(nn)),
+                Brain::Neat(_) => Err(invalid("NEAT genomes can't be exported to NumPy")),
+            }
+        } else if path.ends_with(".rs") || path.ends_with(".h") {
+            match &self.brain {
+                Brain::NN(nn) if path.ends_with(".rs") => std::fs::write(path, codegen::rust(nn)),
+                Brain::NN(nn) => std::fs::write(path, codegen::c(nn)),
+                Brain::Neat(_) => Err(invalid("NEAT genomes can't be exported to source code")),
+            }
+        } else if [".dot", ".gv", ".svg"].iter().any(|e| path.ends_with(e)) {
+            match &self.brain {
+                Brain::NN(nn) if path.ends_with(".svg") => {
+                    std::fs::write(path, diagram::to_svg(nn))
+                }
+                Brain::NN(nn) => std::fs::write(path, diagram::to_dot(nn)),
+                Brain::Neat(_) => Err(invalid("NEAT genomes can't be exported as diagrams")),
+            }
+        } else if path.ends_with(".onnx") {
+            match &self.brain {
+                Brain::NN(nn) => std::fs::write(path, onnx::export(nn)),
+                Brain::Neat(_) => Err(invalid("NEAT genomes can't be exported to ONNX")),
+            }
+        } else {
+            std::fs::write(path, self.export())
+        }
+    }
+
+    // Reads either format, wrapping bare brains in an empty envelope
+    pub fn parse(json: &str) -> Result<Model, ImportError> {
+        let mut value: Value = serde_json::from_str(json)?;
+        let model = match value.get("version").and_then(Value::as_u64) {
+            Some(v) if v > VERSION as u64 => return Err(ImportError::Version(v)),
+            Some(v) => {
+                let field = |value: &mut Value, name| match value.get_mut(name) {
+                    Some(field) => Ok(field.take()),
+                    None => Err(<serde_json::Error as serde::de::Error>::missing_field(name)),
+                };
+                Model {
+                    version: v as u32,
+                    meta: serde_json::from_value(field(&mut value, "meta")?)?,
+                    brain: brain_from_value(field(&mut value, "brain")?)?,
+                }
+            }
+            None => Model {
+                version: VERSION,
+                meta: Metadata::default(),
+                brain: brain_from_value(value)?,
+            },
+        };
+        model.brain.validate()?;
+        Ok(model)
+    }
+
+    // Detects the binary and NumPy formats by their magic numbers
+    pub fn import(path: &str) -> Result<Model, ImportError> {
+        let bytes = std::fs::read(path)?;
+        let model = if bytes.starts_with(&binary::MAGIC) {
+            let model = binary::decode(&bytes)?;
+            model.brain.validate()?;
+            model
+        } else if bytes.starts_with(&npz::MAGIC) {
+            let brain = Brain::NN(npz::import(&bytes)?);
+            brain.validate()?;
+            Model::new(brain, Metadata::default())
+        } else {
+            Model::parse(&String::from_utf8_lossy(&bytes))?
+        };
+        if let Brain::Neat(neat) = &model.brain {
+            neat.register();
+        }
+        Ok(model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        nn::ActivationFunc,
+        player::{INPUTS, OUTPUTS},
+    };
+
+    fn neat() -> Value {
+        let neat = Neat::new(
+            INPUTS,
+            OUTPUTS,
+            0.05,
+            ActivationFunc::Tanh,
+            ActivationFunc::Sigmoid,
+        );
+        serde_json::to_value(neat).unwrap()
+    }
+
+    fn shape_error(value: Value) -> String {
+        match Model::parse(&value.to_string()) {
+            Err(ImportError::Shape(e)) => e,
+            Err(e) => panic!("expected a shape error, got {}", e),
+            Ok(_) => panic!("expected a shape error"),
+        }
+    }
+
+    #[test]
+    fn reports_why_a_network_is_broken() {
+        let nn = json!({
+            "config": [6, 4],
+            "weights": [],
+            "activ_funcs": [],
+            "recurrent": [[[0.0], 1, 1]],
+            "mut_rate": 0.05,
+        });
+        let e = Model::parse(&nn.to_string()).unwrap_err().to_string();
+        assert!(e.contains("no weight matrices"), "{}", e);
+
+        let mut nn: Value = serde_json::from_str(include_str!("../models/model.json")).unwrap();
+        nn["activ_funcs"] = json!(["Tanh"]);
+        let e = Model::parse(&nn.to_string()).unwrap_err().to_string();
+        assert!(e.contains("1 activation functions for 3 layers"), "{}", e);
+
+        let wrapped = json!({ "version": VERSION, "meta": {}, "brain": nn });
+        let e = Model::parse(&wrapped.to_string()).unwrap_err().to_string();
+        assert!(e.contains("1 activation functions for 3 layers"), "{}", e);
+
+        // As the binary and NumPy readers build them, without going through serde
+        let nn: NN = serde_json::from_str(include_str!("../models/model.json")).unwrap();
+        let nn = NN::from_parts(
+            nn.config,
+            nn.weights,
+            vec![ActivationFunc::Tanh],
+            nn.mut_rate,
+            nn.mut_sigma,
+            nn.recurrent,
+            nn.init,
+        );
+        match Brain::NN(nn).validate() {
+            Err(ImportError::Shape(e)) => assert_eq!(e, "1 activation functions for 3 layers"),
+            _ => panic!("expected a shape error"),
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_neat_genomes() {
+        assert!(Model::parse(&neat().to_string()).is_ok());
+
+        let mut bad = neat();
+        bad["nodes"][0]["id"] = json!(INPUTS + OUTPUTS + 7);
+        assert!(shape_error(bad).contains("Input node"));
+
+        let mut bad = neat();
+        bad["nodes"][INPUTS + 1]["id"] = json!(INPUTS + OUTPUTS + 1);
+        assert!(shape_error(bad).contains("Output node"));
+
+        let mut bad = neat();
+        bad["nodes"][1]["id"] = json!(0);
+        assert!(shape_error(bad).contains("more than once"));
+
+        let mut bad = neat();
+        bad["conns"].as_array_mut().unwrap().swap(0, 1);
+        assert!(shape_error(bad).contains("sorted by innovation"));
+
+        // A hidden node feeding itself through a disabled connection
+        let mut bad = neat();
+        let hidden = INPUTS + OUTPUTS + 1;
+        bad["nodes"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!({ "id": hidden, "kind": "Hidden" }));
+        for (innovation, from, to, enabled) in
+            [(100_000, 0, hidden, true), (100_001, hidden, hidden, false)]
+        {
+            bad["conns"].as_array_mut().unwrap().push(json!({
+                "innovation": innovation,
+                "from": from,
+                "to": to,
+                "weight": 1.0,
+                "enabled": enabled,
+            }));
+        }
+        assert!(shape_error(bad).contains("cycle"));
+    }
+}
@@ -0,0 +1,602 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
+
+use macroquad::{prelude::*, rand::gen_range};
+use r::Rng;
+use rand_distr::StandardNormal;
+use serde::{Deserialize, Serialize};
+extern crate rand as r;
+
+use crate::nn::ActivationFunc;
+
+// Chance per mutation of adding a connection, or of splitting one with a new node
+const ADD_CONN: f32 = 0.08;
+const ADD_NODE: f32 = 0.03;
+// Chance that a gene disabled in either parent stays disabled in the child
+const KEEP_DISABLED: f32 = 0.75;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum NodeKind {
+    Input,
+    Bias,
+    Hidden,
+    Output,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Node {
+    pub id: usize,
+    pub kind: NodeKind,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Conn {
+    pub innovation: usize,
+    pub from: usize,
+    pub to: usize,
+    pub weight: f32,
+    pub enabled: bool,
+}
+
+// Innovation numbers shared by every genome in this process, so that the same
+// structural mutation gets the same number and genes can be aligned in crossover
+#[derive(Default)]
+struct Innovations {
+    conns: HashMap<(usize, usize), usize>,
+    // Node id created when splitting the connection with a given innovation
+    splits: HashMap<usize, usize>,
+    next_conn: usize,
+    next_node: usize,
+}
+
+thread_local! {
+    static INNOVATIONS: RefCell<Innovations> = RefCell::new(Innovations::default());
+}
+
+fn innovation(from: usize, to: usize) -> usize {
+    INNOVATIONS.with(|inn| {
+        let mut inn = inn.borrow_mut();
+        if let Some(&i) = inn.conns.get(&(from, to)) {
+            return i;
+        }
+        let i = inn.next_conn;
+        inn.next_conn += 1;
+        inn.conns.insert((from, to), i);
+        i
+    })
+}
+
+fn split_node(innovation: usize) -> usize {
+    INNOVATIONS.with(|inn| {
+        let mut inn = inn.borrow_mut();
+        if let Some(&id) = inn.splits.get(&innovation) {
+            return id;
+        }
+        let id = inn.next_node;
+        inn.next_node += 1;
+        inn.splits.insert(innovation, id);
+        id
+    })
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Neat {
+    // Inputs, then bias, then outputs, then hidden nodes in order of creation
+    pub nodes: Vec<Node>,
+    // Sorted by innovation number
+    pub conns: Vec<Conn>,
+    pub hidden_activ: ActivationFunc,
+    pub output_activ: ActivationFunc,
+    pub mut_rate: f32,
+    pub mut_sigma: f32,
+    // Indices of the nodes in an order they can be evaluated in, each with its
+    // enabled incoming connections and the indices of the nodes they come
+    // from. Built on first use and dropped whenever the structure changes.
+    #[serde(skip)]
+    order: Vec<(usize, Vec<(usize, usize)>)>,
+}
+
+impl Neat {
+    // Starts with every input and the bias connected directly to every output
+    pub fn new(
+        inputs: usize,
+        outputs: usize,
+        mut_rate: f32,
+        hidden_activ: ActivationFunc,
+        output_activ: ActivationFunc,
+    ) -> Self {
+        let mut rng = r::thread_rng();
+        let nodes = (0..inputs)
+            .map(|id| Node {
+                id,
+                kind: NodeKind::Input,
+            })
+            .chain(std::iter::once(Node {
+                id: inputs,
+                kind: NodeKind::Bias,
+            }))
+            .chain((0..outputs).map(|i| Node {
+                id: inputs + 1 + i,
+                kind: NodeKind::Output,
+            }))
+            .collect::<Vec<_>>();
+        let mut conns = Vec::new();
+        for from in 0..=inputs {
+            for to in inputs + 1..inputs + 1 + outputs {
+                conns.push(Conn {
+                    innovation: innovation(from, to),
+                    from,
+                    to,
+                    weight: rng.sample::<f32, StandardNormal>(StandardNormal)
+                        * (2. / (inputs + 1) as f32).sqrt(),
+                    enabled: true,
+                });
+            }
+        }
+        let s = Self {
+            nodes,
+            conns,
+            hidden_activ,
+            output_activ,
+            mut_rate,
+            mut_sigma: 1.,
+            order: Vec::new(),
+        };
+        s.register();
+        s
+    }
+
+    // Makes the shared innovation numbers aware of this genome's genes, so that
+    // a loaded genome doesn't get its ids reused for different structures
+    pub fn register(&self) {
+        INNOVATIONS.with(|inn| {
+            let mut inn = inn.borrow_mut();
+            for c in &self.conns {
+                inn.conns.entry((c.from, c.to)).or_insert(c.innovation);
+                inn.next_conn = inn.next_conn.max(c.innovation + 1);
+            }
+            for n in &self.nodes {
+                inn.next_node = inn.next_node.max(n.id + 1);
+            }
+        });
+    }
+
+    // What a loaded genome has to satisfy for evaluating, drawing and crossing
+    // it over to work: node ids laid out as Neat::new does, connections
+    // between existing nodes in order of innovation, and no cycles
+    pub fn validate(&self, inputs: usize, outputs: usize) -> Result<(), String> {
+        let mut ids = HashSet::new();
+        for n in &self.nodes {
+            if !ids.insert(n.id) {
+                return Err(format!("node {} appears more than once", n.id));
+            }
+            let fits = match n.kind {
+                NodeKind::Input => n.id < inputs,
+                NodeKind::Bias => n.id == inputs,
+                NodeKind::Output => n.id > inputs && n.id <= inputs + outputs,
+                NodeKind::Hidden => n.id > inputs + outputs,
+            };
+            if !fits {
+                return Err(format!("{:?} node has id {}", n.kind, n.id));
+            }
+        }
+        for c in &self.conns {
+            for id in [c.from, c.to] {
+                if !ids.contains(&id) {
+                    return Err(format!(
+                        "connection {} refers to missing node {}",
+                        c.innovation, id
+                    ));
+                }
+            }
+        }
+        if let Some(w) = self
+            .conns
+            .windows(2)
+            .find(|w| w[0].innovation >= w[1].innovation)
+        {
+            return Err(format!(
+                "connection {} comes after {}, but connections must be sorted by innovation",
+                w[1].innovation, w[0].innovation
+            ));
+        }
+
+        // Removes nodes with nothing left feeding them until none are left.
+        // Disabled connections count too, since crossover can enable them again.
+        let mut feeding = ids.iter().map(|&id| (id, 0)).collect::<HashMap<_, _>>();
+        for c in &self.conns {
+            *feeding.get_mut(&c.to).unwrap() += 1;
+        }
+        let mut ready = feeding
+            .iter()
+            .filter(|(_, &n)| n == 0)
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+        let mut removed = 0;
+        while let Some(id) = ready.pop() {
+            removed += 1;
+            for c in self.conns.iter().filter(|c| c.from == id) {
+                let n = feeding.get_mut(&c.to).unwrap();
+                *n -= 1;
+                if *n == 0 {
+                    ready.push(c.to);
+                }
+            }
+        }
+        if removed < self.nodes.len() {
+            return Err("connections form a cycle".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn inputs(&self) -> usize {
+        self.nodes
+            .iter()
+            .filter(|n| n.kind == NodeKind::Input)
+            .count()
+    }
+
+    pub fn outputs(&self) -> usize {
+        self.nodes
+            .iter()
+            .filter(|n| n.kind == NodeKind::Output)
+            .count()
+    }
+
+    // `a` is expected to be the fitter parent: matching genes are picked at
+    // random, disjoint and excess genes are inherited from `a` only
+    pub fn crossover(a: &Neat, b: &Neat) -> Self {
+        let conns = a
+            .conns
+            .iter()
+            .map(|ca| {
+                let mut c = *ca;
+                if let Ok(j) = b
+                    .conns
+                    .binary_search_by_key(&ca.innovation, |c| c.innovation)
+                {
+                    let cb = &b.conns[j];
+                    if gen_range(0., 1.) < 0.5 {
+                        c.weight = cb.weight;
+                    }
+                    c.enabled = (ca.enabled && cb.enabled) || gen_range(0., 1.) >= KEEP_DISABLED;
+                }
+                c
+            })
+            .collect();
+        Self {
+            nodes: a.nodes.to_owned(),
+            conns,
+            hidden_activ: a.hidden_activ,
+            output_activ: a.output_activ,
+            mut_rate: a.mut_rate,
+            mut_sigma: a.mut_sigma,
+            order: Vec::new(),
+        }
+    }
+
+    pub fn mutate(&mut self) {
+        for conn in &mut self.conns {
+            if gen_range(0., 1.) < self.mut_rate {
+                conn.weight =
+                    self.mut_sigma * r::thread_rng().sample::<f32, StandardNormal>(StandardNormal);
+            }
+        }
+        if gen_range(0., 1.) < ADD_CONN {
+            self.add_conn();
+        }
+        if gen_range(0., 1.) < ADD_NODE {
+            self.add_node();
+        }
+    }
+
+    fn add_conn(&mut self) {
+        for _ in 0..20 {
+            let from = self.nodes[gen_range(0, self.nodes.len())];
+            let to = self.nodes[gen_range(0, self.nodes.len())];
+            if from.id == to.id
+                || from.kind == NodeKind::Output
+                || matches!(to.kind, NodeKind::Input | NodeKind::Bias)
+                || self
+                    .conns
+                    .iter()
+                    .any(|c| c.from == from.id && c.to == to.id)
+                || self.reaches(to.id, from.id)
+            {
+                continue;
+            }
+            self.conns.push(Conn {
+                innovation: innovation(from.id, to.id),
+                from: from.id,
+                to: to.id,
+                weight: self.mut_sigma
+                    * r::thread_rng().sample::<f32, StandardNormal>(StandardNormal),
+                enabled: true,
+            });
+            self.conns.sort_by_key(|c| c.innovation);
+            self.order.clear();
+            return;
+        }
+    }
+
+    fn add_node(&mut self) {
+        let enabled = self
+            .conns
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.enabled)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        if enabled.is_empty() {
+            return;
+        }
+        let old = self.conns[enabled[gen_range(0, enabled.len())]];
+        let id = split_node(old.innovation);
+        if self.nodes.iter().any(|n| n.id == id) {
+            return;
+        }
+        for c in &mut self.conns {
+            if c.innovation == old.innovation {
+                c.enabled = false;
+            }
+        }
+        self.nodes.push(Node {
+            id,
+            kind: NodeKind::Hidden,
+        });
+        // The new path starts out computing (almost) the same as the old connection
+        for (from, to, weight) in [(old.from, id, 1.), (id, old.to, old.weight)] {
+            self.conns.push(Conn {
+                innovation: innovation(from, to),
+                from,
+                to,
+                weight,
+                enabled: true,
+            });
+        }
+        self.conns.sort_by_key(|c| c.innovation);
+        self.order.clear();
+    }
+
+    // Whether there is a path from `from` to `to`, counting disabled connections
+    // too since crossover may enable them again
+    fn reaches(&self, from: usize, to: usize) -> bool {
+        let mut stack = vec![from];
+        let mut seen = HashSet::new();
+        while let Some(id) = stack.pop() {
+            if id == to {
+                return true;
+            }
+            if seen.insert(id) {
+                stack.extend(self.conns.iter().filter(|c| c.from == id).map(|c| c.to));
+            }
+        }
+        false
+    }
+
+    // Longest path from an input to each node. Outputs are pushed to the last column.
+    fn depths(&self) -> HashMap<usize, usize> {
+        let mut depths = self
+            .nodes
+            .iter()
+            .map(|n| (n.id, 0))
+            .collect::<HashMap<_, _>>();
+        for _ in 0..self.nodes.len() {
+            let mut changed = false;
+            for c in &self.conns {
+                let d = depths[&c.from] + 1;
+                if depths[&c.to] < d {
+                    depths.insert(c.to, d);
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        let last = depths.values().copied().max().unwrap_or(0).max(1);
+        for n in &self.nodes {
+            if n.kind == NodeKind::Output {
+                depths.insert(n.id, last);
+            }
+        }
+        depths
+    }
+
+    fn build_order(&mut self) {
+        let depths = self.depths();
+        let index = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (n.id, i))
+            .collect::<HashMap<_, _>>();
+        let mut order = (0..self.nodes.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| depths[&self.nodes[i].id]);
+        self.order = order
+            .into_iter()
+            .map(|i| {
+                let incoming = self
+                    .conns
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, c)| c.enabled && c.to == self.nodes[i].id)
+                    .map(|(k, c)| (k, index[&c.from]))
+                    .collect();
+                (i, incoming)
+            })
+            .collect();
+    }
+
+    pub fn feed_forward(&mut self, inputs: &[f32]) -> Vec<f32> {
+        if self.order.is_empty() {
+            self.build_order();
+        }
+        let mut values = vec![0.; self.nodes.len()];
+        for (i, incoming) in &self.order {
+            let n = &self.nodes[*i];
+            values[*i] = match n.kind {
+                NodeKind::Input => inputs[n.id],
+                NodeKind::Bias => 1.,
+                _ => {
+                    let x = incoming
+                        .iter()
+                        .map(|&(k, from)| self.conns[k].weight * values[from])
+                        .sum::<f32>();
+                    if n.kind == NodeKind::Output {
+                        self.output_activ.apply(x)
+                    } else {
+                        self.hidden_activ.apply(x)
+                    }
+                }
+            };
+        }
+        self.nodes
+            .iter()
+            .zip(&values)
+            .filter(|(n, _)| n.kind == NodeKind::Output)
+            .map(|(_, &v)| v)
+            .collect()
+    }
+
+    pub fn draw(&self, width: f32, height: f32, inputs: &[f32], outputs: &[f32], bias: bool) {
+        draw_rectangle_lines(-width * 0.5, -height * 0.5, width, height, 2., WHITE);
+
+        let width = width * 0.8;
+        let height = height * 0.8;
+        let depths = self.depths();
+        let last = depths.values().copied().max().unwrap();
+        let visible = |n: &Node| bias || n.kind != NodeKind::Bias;
+
+        let mut columns: Vec<Vec<&Node>> = vec![Vec::new(); last + 1];
+        for n in self.nodes.iter().filter(|n| visible(n)) {
+            columns[depths[&n.id]].push(n);
+        }
+        let vspace = height / (columns.iter().map(|c| c.len()).max().unwrap() - 1).max(1) as f32;
+        let mut pos: HashMap<usize, (f32, f32)> = HashMap::new();
+        for (i, column) in columns.iter().enumerate() {
+            for (j, n) in column.iter().enumerate() {
+                pos.insert(
+                    n.id,
+                    (
+                        i as f32 * width / last as f32 - width * 0.5,
+                        j as f32 * vspace - (vspace * (column.len() as f32 - 1.)) * 0.5,
+                    ),
+                );
+            }
+        }
+
+        for c in self.conns.iter().filter(|c| c.enabled) {
+            if let (Some(p1), Some(p2)) = (pos.get(&c.from), pos.get(&c.to)) {
+                let col = if c.weight < 0. { 0. } else { 1. };
+                draw_line(
+                    p1.0,
+                    p1.1,
+                    p2.0,
+                    p2.1,
+                    1.5,
+                    Color::new(1., col, col, c.weight.abs()),
+                );
+            }
+        }
+
+        for n in self.nodes.iter().filter(|n| visible(n)) {
+            let p = pos[&n.id];
+            draw_circle(p.0, p.1, 10., WHITE);
+            draw_circle(p.0, p.1, 8., BLACK);
+            let value = match n.kind {
+                NodeKind::Input if inputs.len() > n.id => Some(inputs[n.id]),
+                NodeKind::Bias if !inputs.is_empty() => Some(1.),
+                NodeKind::Output if !outputs.is_empty() => Some(outputs[n.id - self.inputs() - 1]),
+                _ => None,
+            };
+            if let Some(value) = value {
+                let c = if value < 0. { 0. } else { 1. };
+                draw_circle(p.0, p.1, 8., Color::new(1., c, c, value.abs()));
+                if n.kind == NodeKind::Output {
+                    draw_text(&format!("{:.2}", value), p.0 + 14., p.1 + 4., 16., WHITE);
+                } else {
+                    draw_text(
+                        &format!("{:.2}", value),
+                        p.0 - if value < 0. { 50. } else { 42. },
+                        p.1 + 4.,
+                        16.,
+                        WHITE,
+                    );
+                }
+            }
+        }
+
+        draw_rectangle(width * 0.47, height * 0.47, 10., 10., RED);
+        let params = TextParams {
+            font_size: 40,
+            font_scale: 0.5,
+            ..Default::default()
+        };
+        draw_text_ex("-ve", width * 0.47 + 20., height * 0.47 + 10., params);
+        draw_rectangle(width * 0.47, height * 0.47 + 20., 10., 10., WHITE);
+        draw_text_ex("+ve", width * 0.47 + 20., height * 0.47 + 30., params);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn genome() -> Neat {
+        Neat::new(3, 2, 0.1, ActivationFunc::Tanh, ActivationFunc::Sigmoid)
+    }
+
+    #[test]
+    fn splitting_the_same_connection_matches_innovations() {
+        // With a single enabled connection both genomes have to split it
+        let mut a = genome();
+        for c in a.conns.iter_mut().skip(1) {
+            c.enabled = false;
+        }
+        let mut b = a.clone();
+        a.add_node();
+        b.add_node();
+        assert_eq!(a.nodes.len(), b.nodes.len());
+        assert_eq!(a.nodes.last().unwrap().id, b.nodes.last().unwrap().id);
+        let genes = |n: &Neat| {
+            n.conns
+                .iter()
+                .map(|c| (c.innovation, c.from, c.to))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(genes(&a), genes(&b));
+        assert!(!a.conns[0].enabled);
+        assert!(a.validate(3, 2).is_ok());
+    }
+
+    #[test]
+    fn crossover_aligns_genes_by_innovation() {
+        let base = genome();
+        let mut a = base.clone();
+        let mut b = base.clone();
+        for c in &mut b.conns {
+            c.weight += 10.;
+        }
+        // Genes only the fitter parent has
+        a.add_node();
+        assert_eq!(a.conns.len(), b.conns.len() + 2);
+        let child = Neat::crossover(&a, &b);
+        assert_eq!(child.nodes.len(), a.nodes.len());
+        assert_eq!(
+            child.conns.iter().map(|c| c.innovation).collect::<Vec<_>>(),
+            a.conns.iter().map(|c| c.innovation).collect::<Vec<_>>()
+        );
+        for (c, ca) in child.conns.iter().zip(&a.conns) {
+            match b.conns.iter().find(|cb| cb.innovation == c.innovation) {
+                Some(cb) => assert!(c.weight == ca.weight || c.weight == cb.weight),
+                None => assert_eq!(c.weight, ca.weight),
+            }
+        }
+        assert!(child.validate(3, 2).is_ok());
+        let mut child = child;
+        assert_eq!(child.feed_forward(&[0.5, -0.5, 1.]).len(), 2);
+    }
+}
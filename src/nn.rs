@@ -3,6 +3,8 @@ use nalgebra::*;
 use r::Rng;
 use rand_distr::StandardNormal;
 use serde::{Deserialize, Serialize};
+
+use crate::player::INPUT_NAMES;
 extern crate rand as r;
 
 #[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
@@ -11,20 +13,367 @@ pub enum ActivationFunc {
     ReLU,
     Sigmoid,
     Tanh,
+    LeakyReLU,
+    Elu,
+    Softsign,
+    Gaussian,
+    Sine,
+    Step,
+    Identity,
+    Swish,
+}
+
+impl ActivationFunc {
+    pub const ALL: [ActivationFunc; 11] = [
+        ActivationFunc::ReLU,
+        ActivationFunc::Sigmoid,
+        ActivationFunc::Tanh,
+        ActivationFunc::LeakyReLU,
+        ActivationFunc::Elu,
+        ActivationFunc::Softsign,
+        ActivationFunc::Gaussian,
+        ActivationFunc::Sine,
+        ActivationFunc::Step,
+        ActivationFunc::Identity,
+        ActivationFunc::Swish,
+    ];
+    pub const NAMES: [&'static str; 11] = [
+        "ReLU", "Sigm", "Tanh", "Leaky", "ELU", "Softsign", "Gauss", "Sine", "Step", "Ident",
+        "Swish",
+    ];
+
+    pub fn apply(self, x: f32) -> f32 {
+        match self {
+            ActivationFunc::ReLU => x.max(0.),
+            ActivationFunc::Sigmoid => 1. / (1. + (-x).exp()),
+            ActivationFunc::Tanh => x.tanh(),
+            ActivationFunc::LeakyReLU => x.max(0.01 * x),
+            ActivationFunc::Elu => {
+                if x > 0. {
+                    x
+                } else {
+                    x.exp() - 1.
+                }
+            }
+            ActivationFunc::Softsign => x / (1. + x.abs()),
+            ActivationFunc::Gaussian => (-x * x).exp(),
+            ActivationFunc::Sine => x.sin(),
+            ActivationFunc::Step => {
+                if x > 0. {
+                    1.
+                } else {
+                    0.
+                }
+            }
+            ActivationFunc::Identity => x,
+            ActivationFunc::Swish => x / (1. + (-x).exp()),
+        }
+    }
+
+    // Slope at x, the value before activation
+    pub fn derivative(self, x: f32) -> f32 {
+        match self {
+            ActivationFunc::ReLU => {
+                if x > 0. {
+                    1.
+                } else {
+                    0.
+                }
+            }
+            ActivationFunc::Sigmoid => {
+                let s = 1. / (1. + (-x).exp());
+                s * (1. - s)
+            }
+            ActivationFunc::Tanh => 1. - x.tanh().powi(2),
+            ActivationFunc::LeakyReLU => {
+                if x > 0. {
+                    1.
+                } else {
+                    0.01
+                }
+            }
+            ActivationFunc::Elu => {
+                if x > 0. {
+                    1.
+                } else {
+                    x.exp()
+                }
+            }
+            ActivationFunc::Softsign => 1. / (1. + x.abs()).powi(2),
+            ActivationFunc::Gaussian => -2. * x * (-x * x).exp(),
+            ActivationFunc::Sine => x.cos(),
+            ActivationFunc::Step => 0.,
+            ActivationFunc::Identity => 1.,
+            ActivationFunc::Swish => {
+                let s = 1. / (1. + (-x).exp());
+                s + x * s * (1. - s)
+            }
+        }
+    }
+
+    // Output value above which a key counts as pressed
+    pub fn threshold(self) -> f32 {
+        match self {
+            // Always positive, so 0 would keep every key pressed
+            ActivationFunc::Sigmoid => 0.85,
+            ActivationFunc::Gaussian | ActivationFunc::Step => 0.5,
+            // Centered around 0, or 0 for any negative input
+            _ => 0.,
+        }
+    }
+}
+
+// Activations of the non-empty hidden layers followed by the output one, from
+// one activation per hidden layer slot (including empty ones) plus the output
+pub fn layer_activs(hlayers: &[usize], activs: &[ActivationFunc]) -> Vec<ActivationFunc> {
+    hlayers
+        .iter()
+        .zip(activs)
+        .filter(|(&n, _)| n != 0)
+        .map(|(_, &a)| a)
+        .chain(activs.last().copied())
+        .collect()
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "RawNN")]
 pub struct NN {
     pub config: Vec<usize>,
     pub weights: Vec<DMatrix<f32>>,
-    pub activ_func: ActivationFunc,
+    // One per layer of weights, the last one being the output layer's
+    pub activ_funcs: Vec<ActivationFunc>,
     pub mut_rate: f32,
+    // Standard deviation of the values mutated weights are redrawn from
+    pub mut_sigma: f32,
+    // Elman-style feedback of each hidden layer's previous activations into
+    // itself. Empty for plain feed-forward networks.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub recurrent: Vec<DMatrix<f32>>,
+    // How the weights were first drawn
+    pub init: WeightInit,
+    // Hidden layer activations from the previous feed_forward call
+    #[serde(skip)]
+    pub state: Vec<Vec<f32>>,
+    // The state before that, so the last call can be traced exactly
+    #[serde(skip)]
+    prev_state: Vec<Vec<f32>>,
+    #[serde(skip)]
+    workspace: Workspace,
 }
 
-impl NN {
-    // Vec of number of neurons in input, hidden 1, hidden 2, ..., output layers
-    pub fn new(config: Vec<usize>, mut_rate: f32, activ: ActivationFunc) -> Self {
+// Every layer's values during one forward pass, without bias neurons. The
+// input layer's sums are the inputs themselves.
+pub struct Trace {
+    pub sums: Vec<Vec<f32>>,
+    pub activations: Vec<Vec<f32>>,
+}
+
+// What the pointer is over in the brain panel: a neuron by layer and index, or
+// the weight from neuron k of layer i to neuron j of layer i + 1
+#[derive(Clone, Copy, PartialEq)]
+pub enum Hover {
+    Neuron(usize, usize),
+    Weight(usize, usize, usize),
+}
+
+// The layer and index of the neuron at the other end of a connection, and its
+// weight
+pub type Link = (usize, usize, f32);
+
+// Scratch buffers for forward passes: one matrix per layer, stored column by
+// column, with a column per network and a row per neuron including the bias.
+// Reusing one keeps feed_forward from allocating every frame.
+#[derive(Clone, Debug, Default)]
+pub struct Workspace {
+    layers: Vec<Vec<f32>>,
+}
+
+impl Workspace {
+    pub fn new(config: &[usize], count: usize) -> Self {
+        Self {
+            layers: config.iter().map(|&n| vec![0.; n * count]).collect(),
+        }
+    }
+
+    // Only reallocates when handed more networks than it has room for, or a
+    // different number of layers
+    fn fit(&mut self, config: &[usize], count: usize) {
+        if self.layers.len() != config.len()
+            || self
+                .layers
+                .iter()
+                .zip(config)
+                .any(|(l, &n)| l.len() < n * count)
+        {
+            *self = Workspace::new(config, count);
+        }
+    }
+}
+
+// How new weight matrices are filled. Fan-in counts the bias column.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum WeightInit {
+    #[default]
+    He,
+    XavierUniform,
+    XavierNormal,
+    // Uniform in [-a, a]
+    Uniform(f32),
+    Zeros,
+    Orthogonal,
+}
+
+impl WeightInit {
+    pub const NAMES: [&'static str; 6] = [
+        "He",
+        "Xavier U",
+        "Xavier N",
+        "Uniform",
+        "Zeros",
+        "Orthogonal",
+    ];
+
+    pub fn from_index(i: usize, range: f32) -> Self {
+        match i {
+            1 => WeightInit::XavierUniform,
+            2 => WeightInit::XavierNormal,
+            3 => WeightInit::Uniform(range),
+            4 => WeightInit::Zeros,
+            5 => WeightInit::Orthogonal,
+            _ => WeightInit::He,
+        }
+    }
+
+    pub fn index(self) -> usize {
+        match self {
+            WeightInit::He => 0,
+            WeightInit::XavierUniform => 1,
+            WeightInit::XavierNormal => 2,
+            WeightInit::Uniform(_) => 3,
+            WeightInit::Zeros => 4,
+            WeightInit::Orthogonal => 5,
+        }
+    }
+
+    // A rows x cols matrix for a layer with `cols` inputs and `rows` outputs
+    pub fn matrix(self, rows: usize, cols: usize) -> DMatrix<f32> {
         let mut rng = r::thread_rng();
+        let normal = |rng: &mut r::rngs::ThreadRng| {
+            DMatrix::<f32>::from_distribution(rows, cols, &StandardNormal, rng)
+        };
+        let uniform = |rng: &mut r::rngs::ThreadRng, a: f32| {
+            DMatrix::<f32>::from_fn(rows, cols, |_, _| rng.gen_range(-a..=a))
+        };
+        let (fan_in, fan_out) = (cols as f32, rows as f32);
+        match self {
+            WeightInit::He => normal(&mut rng) * (2. / fan_in).sqrt(),
+            WeightInit::XavierUniform => uniform(&mut rng, (6. / (fan_in + fan_out)).sqrt()),
+            WeightInit::XavierNormal => normal(&mut rng) * (2. / (fan_in + fan_out)).sqrt(),
+            WeightInit::Uniform(a) => uniform(&mut rng, a.abs()),
+            WeightInit::Zeros => DMatrix::zeros(rows, cols),
+            WeightInit::Orthogonal => {
+                // Q from the QR decomposition of a Gaussian matrix, with signs
+                // fixed so the result is uniformly distributed
+                let tall = rows >= cols;
+                let a = if tall {
+                    normal(&mut rng)
+                } else {
+                    normal(&mut rng).transpose()
+                };
+                let qr = a.qr();
+                let signs = qr.r().diagonal().map(|x| if x < 0. { -1. } else { 1. });
+                let mut q = qr.q();
+                for (mut col, s) in q.column_iter_mut().zip(signs.iter()) {
+                    col *= *s;
+                }
+                if tall {
+                    q
+                } else {
+                    q.transpose()
+                }
+            }
+        }
+    }
+}
+
+fn default_sigma() -> f32 {
+    1.
+}
+
+// Model files as stored on disk, including older formats
+#[derive(Deserialize)]
+struct RawNN {
+    config: Vec<usize>,
+    weights: Vec<DMatrix<f32>>,
+    // Older models use a single activation for every layer
+    activ_func: Option<ActivationFunc>,
+    activ_funcs: Option<Vec<ActivationFunc>>,
+    mut_rate: f32,
+    #[serde(default = "default_sigma")]
+    mut_sigma: f32,
+    #[serde(default)]
+    recurrent: Vec<DMatrix<f32>>,
+    #[serde(default)]
+    init: WeightInit,
+}
+
+impl TryFrom<RawNN> for NN {
+    type Error = String;
+
+    fn try_from(raw: RawNN) -> Result<Self, Self::Error> {
+        let hidden = raw
+            .weights
+            .len()
+            .checked_sub(1)
+            .ok_or("no weight matrices".to_string())?;
+        let activ_funcs = match (raw.activ_funcs, raw.activ_func) {
+            (Some(activs), _) => activs,
+            (None, Some(activ)) => vec![activ; raw.weights.len()],
+            (None, None) => return Err("missing field `activ_funcs`".to_string()),
+        };
+        if activ_funcs.len() != raw.weights.len() {
+            return Err(format!(
+                "{} activation functions for {} layers",
+                activ_funcs.len(),
+                raw.weights.len()
+            ));
+        }
+        if !raw.recurrent.is_empty()
+            && (raw.recurrent.len() != hidden
+                || raw
+                    .recurrent
+                    .iter()
+                    .zip(&raw.weights)
+                    .any(|(r, w)| r.shape() != (w.nrows(), w.nrows())))
+        {
+            return Err("recurrent weights don't match the hidden layers".to_string());
+        }
+        Ok(NN::from_parts(
+            raw.config,
+            raw.weights,
+            activ_funcs,
+            raw.mut_rate,
+            raw.mut_sigma,
+            raw.recurrent,
+            raw.init,
+        ))
+    }
+}
+
+impl NN {
+    // Vec of number of neurons in input, hidden 1, hidden 2, ..., output layers,
+    // and the activation of every layer but the input
+    pub fn new(
+        config: Vec<usize>,
+        mut_rate: f32,
+        activs: Vec<ActivationFunc>,
+        init: WeightInit,
+    ) -> Self {
+        assert_eq!(
+            config.len() - 1,
+            activs.len(),
+            "Need an activation per layer."
+        );
 
         Self {
             config: config
@@ -33,19 +382,150 @@ impl NN {
                 .map(|(i, &x)| if i != config.len() - 1 { x + 1 } else { x })
                 .collect(),
 
-            // He-et-al Initialization
             weights: config
                 .iter()
                 .zip(config.iter().skip(1))
-                .map(|(&curr, &last)| {
-                    // DMatrix::from_fn(last, curr + 1, |_, _| gen_range(-1., 1.))
-                    DMatrix::<f32>::from_distribution(last, curr + 1, &StandardNormal, &mut rng)
-                        * (2. / last as f32).sqrt()
-                })
+                .map(|(&curr, &last)| init.matrix(last, curr + 1))
                 .collect(),
 
             mut_rate,
-            activ_func: activ,
+            mut_sigma: default_sigma(),
+            activ_funcs: activs,
+            recurrent: Vec::new(),
+            init,
+            state: Vec::new(),
+            prev_state: Vec::new(),
+            workspace: Workspace::default(),
+        }
+    }
+
+    // Rebuilds a saved network. `config` already includes the bias neurons.
+    pub fn from_parts(
+        config: Vec<usize>,
+        weights: Vec<DMatrix<f32>>,
+        activ_funcs: Vec<ActivationFunc>,
+        mut_rate: f32,
+        mut_sigma: f32,
+        recurrent: Vec<DMatrix<f32>>,
+        init: WeightInit,
+    ) -> Self {
+        Self {
+            config,
+            weights,
+            activ_funcs,
+            mut_rate,
+            mut_sigma,
+            recurrent,
+            init,
+            state: Vec::new(),
+            prev_state: Vec::new(),
+            workspace: Workspace::default(),
+        }
+    }
+
+    // Shapes of the weight matrices, then the recurrent ones, for a config
+    // that includes bias neurons like NN::config
+    fn shapes(config: &[usize], recurrent: bool) -> Vec<(usize, usize)> {
+        let rows = |i: usize| config[i + 1] - (i + 2 < config.len()) as usize;
+        let mut shapes = (0..config.len() - 1)
+            .map(|i| (rows(i), config[i]))
+            .collect::<Vec<_>>();
+        if recurrent {
+            shapes.extend((0..config.len() - 2).map(|i| (rows(i), rows(i))));
+        }
+        shapes
+    }
+
+    pub fn genome_len(config: &[usize], recurrent: bool) -> usize {
+        NN::shapes(config, recurrent)
+            .iter()
+            .map(|(r, c)| r * c)
+            .sum()
+    }
+
+    // Every weight as one flat vector: each layer column by column, then the
+    // recurrent weights
+    pub fn genome(&self) -> Vec<f32> {
+        self.weights
+            .iter()
+            .chain(&self.recurrent)
+            .flat_map(|m| m.iter().copied())
+            .collect()
+    }
+
+    pub fn set_genome(&mut self, genome: &[f32]) {
+        assert_eq!(
+            genome.len(),
+            NN::genome_len(&self.config, !self.recurrent.is_empty()),
+            "Genome doesn't fit the network."
+        );
+        let mut rest = genome;
+        for m in self.weights.iter_mut().chain(&mut self.recurrent) {
+            let (head, tail) = rest.split_at(m.len());
+            m.copy_from_slice(head);
+            rest = tail;
+        }
+    }
+
+    // The inverse of genome
+    pub fn from_genome(
+        config: Vec<usize>,
+        activ_funcs: Vec<ActivationFunc>,
+        recurrent: bool,
+        genome: &[f32],
+        mut_rate: f32,
+        mut_sigma: f32,
+    ) -> Result<NN, String> {
+        if config.len() < 2 || config.contains(&0) {
+            return Err(format!("layer sizes {:?}", config));
+        }
+        if activ_funcs.len() != config.len() - 1 {
+            return Err(format!(
+                "{} activation functions for {} layers",
+                activ_funcs.len(),
+                config.len() - 1
+            ));
+        }
+        let len = NN::genome_len(&config, recurrent);
+        if genome.len() != len {
+            return Err(format!(
+                "genome has {} weights, config needs {}",
+                genome.len(),
+                len
+            ));
+        }
+        let mut matrices = Vec::new();
+        let mut rest = genome;
+        for (r, c) in NN::shapes(&config, recurrent) {
+            let (head, tail) = rest.split_at(r * c);
+            matrices.push(DMatrix::from_column_slice(r, c, head));
+            rest = tail;
+        }
+        let recurrent = matrices.split_off(config.len() - 1);
+        Ok(NN::from_parts(
+            config,
+            matrices,
+            activ_funcs,
+            mut_rate,
+            mut_sigma,
+            recurrent,
+            WeightInit::default(),
+        ))
+    }
+
+    // Adds feedback connections from every hidden layer to itself
+    pub fn add_recurrence(&mut self) {
+        self.recurrent = self
+            .weights
+            .iter()
+            .take(self.weights.len() - 1)
+            .map(|w| self.init.matrix(w.nrows(), w.nrows()))
+            .collect();
+    }
+
+    pub fn reset_state(&mut self) {
+        for h in self.state.iter_mut().chain(&mut self.prev_state) {
+            h.fill(0.);
         }
     }
 
@@ -53,8 +533,10 @@ impl NN {
         assert_eq!(a.config, b.config, "NN configs not same.");
         Self {
             config: a.config.to_owned(),
-            activ_func: a.activ_func,
+            activ_funcs: a.activ_funcs.to_owned(),
             mut_rate: a.mut_rate,
+            mut_sigma: a.mut_sigma,
+            init: a.init,
             weights: a
                 .weights
                 .iter()
@@ -66,106 +548,263 @@ impl NN {
                     )
                 })
                 .collect(),
+            recurrent: a
+                .recurrent
+                .iter()
+                .zip(b.recurrent.iter())
+                .map(|(m1, m2)| {
+                    m1.zip_map(
+                        m2,
+                        |ele1, ele2| if gen_range(0., 1.) < 0.5 { ele1 } else { ele2 },
+                    )
+                })
+                .collect(),
+            state: Vec::new(),
+            prev_state: Vec::new(),
+            workspace: Workspace::default(),
         }
     }
 
     pub fn mutate(&mut self) {
-        for weight in &mut self.weights {
+        for weight in self.weights.iter_mut().chain(self.recurrent.iter_mut()) {
             for ele in weight {
                 if gen_range(0., 1.) < self.mut_rate {
                     // *ele += gen_range(-1., 1.);
                     // *ele = gen_range(-1., 1.);
-                    *ele = r::thread_rng().sample::<f32, StandardNormal>(StandardNormal);
+                    *ele = self.mut_sigma
+                        * r::thread_rng().sample::<f32, StandardNormal>(StandardNormal);
                 }
             }
         }
     }
 
-    pub fn feed_forward(&self, inputs: &Vec<f32>) -> Vec<f32> {
-        // println!("inputs: {:?}", inputs);
-        let mut y = DMatrix::from_vec(inputs.len(), 1, inputs.to_vec());
-        for i in 0..self.config.len() - 1 {
-            y = (&self.weights[i] * y.insert_row(self.config[i] - 1, 1.)).map(|x| {
-                match self.activ_func {
-                    ActivationFunc::ReLU => x.max(0.),
-                    ActivationFunc::Sigmoid => 1. / (1. + (-x).exp()),
-                    ActivationFunc::Tanh => x.tanh(),
+    // Writes into `outputs` using the network's own workspace, so nothing is
+    // allocated once it has warmed up
+    pub fn feed_forward_into(&mut self, inputs: &[f32], outputs: &mut [f32]) {
+        let mut ws = std::mem::take(&mut self.workspace);
+        self.feed_forward_with(&mut ws, inputs, outputs);
+        self.workspace = ws;
+    }
+
+    pub fn feed_forward_with(&mut self, ws: &mut Workspace, inputs: &[f32], outputs: &mut [f32]) {
+        ws.fit(&self.config, 1);
+        let last = self.config.len() - 1;
+        ws.layers[0][..inputs.len()].copy_from_slice(inputs);
+        ws.layers[0][inputs.len()] = 1.;
+        for i in 0..last {
+            let (prev, next) = ws.layers.split_at_mut(i + 1);
+            self.forward_layer(i, &prev[i], &mut next[0]);
+        }
+        outputs.copy_from_slice(&ws.layers[last][..outputs.len()]);
+    }
+
+    // Fills layer i + 1 from layer i, both including the bias neuron if they
+    // have one, and keeps the hidden state of recurrent layers
+    fn forward_layer(&mut self, i: usize, x: &[f32], y: &mut [f32]) {
+        let last = self.config.len() - 1;
+        let rows = self.weights[i].nrows();
+        let z = &mut y[..rows];
+        z.fill(0.);
+        for (col, &xc) in self.weights[i].column_iter().zip(x) {
+            for (zr, &w) in z.iter_mut().zip(col.iter()) {
+                *zr += w * xc;
+            }
+        }
+        if let (Some(r), Some(h)) = (self.recurrent.get(i), self.state.get(i)) {
+            // Summed on its own and then added, like the MatMul and Add of the
+            // ONNX export
+            for (j, zr) in z.iter_mut().enumerate() {
+                let mut rz = 0.;
+                for (&w, &hc) in r.row(j).iter().zip(h) {
+                    rz += w * hc;
                 }
-            });
+                *zr += rz;
+            }
+        }
+        for zr in z.iter_mut() {
+            *zr = self.activ_funcs[i].apply(*zr);
+        }
+        if i < self.recurrent.len() {
+            if i < self.state.len() {
+                std::mem::swap(&mut self.state[i], &mut self.prev_state[i]);
+                self.state[i].copy_from_slice(z);
+            } else {
+                self.state.push(z.to_vec());
+                self.prev_state.push(vec![0.; z.len()]);
+            }
+        }
+        if i + 1 < last {
+            y[rows] = 1.;
         }
-        y.column(0).data.into_slice().to_vec()
     }
 
-    pub fn draw(&self, width: f32, height: f32, inputs: &Vec<f32>, outputs: &Vec<f32>, bias: bool) {
-        draw_rectangle_lines(-width * 0.5, -height * 0.5, width, height, 2., WHITE);
+    // Runs many networks at once. Networks of the same shape are stacked into
+    // one matrix per layer, a column each, and evaluated a layer at a time.
+    // The outputs are the same as from feed_forward_with.
+    pub fn feed_forward_batch<'a>(
+        ws: &mut Workspace,
+        batch: impl IntoIterator<Item = (&'a mut NN, &'a [f32], &'a mut [f32])>,
+    ) {
+        let mut rest = batch.into_iter().collect::<Vec<_>>();
+        while !rest.is_empty() {
+            let config = rest[0].0.config.clone();
+            let (mut group, others): (Vec<_>, Vec<_>) =
+                rest.into_iter().partition(|(nn, _, _)| nn.config == config);
+            rest = others;
+
+            let last = config.len() - 1;
+            ws.fit(&config, group.len());
+            for ((_, inputs, _), x) in group.iter().zip(ws.layers[0].chunks_mut(config[0])) {
+                x[..inputs.len()].copy_from_slice(inputs);
+                x[inputs.len()] = 1.;
+            }
+            for i in 0..last {
+                let (prev, next) = ws.layers.split_at_mut(i + 1);
+                let columns = prev[i]
+                    .chunks(config[i])
+                    .zip(next[0].chunks_mut(config[i + 1]));
+                for ((nn, _, _), (x, y)) in group.iter_mut().zip(columns) {
+                    nn.forward_layer(i, x, y);
+                }
+            }
+            for ((_, _, outputs), y) in group.iter_mut().zip(ws.layers[last].chunks(config[last])) {
+                outputs.copy_from_slice(y);
+            }
+        }
+    }
 
+    // Replays the last feed_forward call, which must have been given these
+    // inputs, keeping every layer's values
+    pub fn trace(&self, inputs: &[f32]) -> Trace {
+        let mut trace = Trace {
+            sums: vec![inputs.to_vec()],
+            activations: vec![inputs.to_vec()],
+        };
+        for (i, w) in self.weights.iter().enumerate() {
+            let x = DMatrix::from_iterator(
+                w.ncols(),
+                1,
+                trace.activations[i].iter().copied().chain(Some(1.)),
+            );
+            let mut z = w * x;
+            if let (Some(r), Some(h)) = (self.recurrent.get(i), self.prev_state.get(i)) {
+                z += r * DMatrix::from_column_slice(h.len(), 1, h);
+            }
+            let y = z.map(|x| self.activ_funcs[i].apply(x));
+            trace.sums.push(z.iter().copied().collect());
+            trace.activations.push(y.iter().copied().collect());
+        }
+        trace
+    }
+
+    // Neuron positions layer by layer, each non-output layer ending with its
+    // bias neuron when `bias` is set
+    pub fn layout(&self, width: f32, height: f32, bias: bool) -> Vec<Vec<(f32, f32)>> {
         let width = width * 0.8;
         let height = height * 0.8;
         let vspace = height / (self.config.iter().max().unwrap() - 1) as f32;
-        let mut p1s: Vec<(f32, f32)>;
-        let mut p2s: Vec<(f32, f32)> = Vec::new();
-        for (i, layer) in self
-            .config
+        let n = self.config.len();
+        self.config
             .iter()
-            .take(self.config.len() - 1)
-            .map(|x| x - if bias { 0 } else { 1 })
-            .chain(self.config.last().map(|&x| x))
             .enumerate()
-        {
-            p1s = p2s;
-            p2s = Vec::new();
-            for neuron in 0..layer {
-                p2s.push((
-                    i as f32 * width / (self.config.len() - 1) as f32 - width * 0.5,
-                    neuron as f32 * vspace - (vspace * (layer - 1) as f32) * 0.5,
-                ));
-            }
-            for (k, j, p1, p2) in p1s.iter().enumerate().flat_map(|(k, x)| {
-                p2s.iter()
-                    .take(
-                        p2s.len()
-                            - if i == self.config.len() - 1 || !bias {
-                                0
-                            } else {
-                                1
-                            },
-                    )
-                    .enumerate()
-                    .map(move |(j, y)| (k, j, *x, *y))
-            }) {
-                let weight = *self.weights[i - 1].index((j, k));
-                let c = if weight < 0. { 0. } else { 1. };
-                draw_line(
-                    p1.0,
-                    p1.1,
-                    p2.0,
-                    p2.1,
-                    1.5,
-                    Color::new(1., c, c, weight.abs()),
-                );
-            }
-
-            let mut inputs = inputs.to_vec();
-            inputs.push(1.);
-
-            for (j, p) in p1s.iter().enumerate() {
+            .map(|(i, &size)| {
+                let layer = if i < n - 1 && !bias { size - 1 } else { size };
+                (0..layer)
+                    .map(|neuron| {
+                        (
+                            i as f32 * width / (n - 1) as f32 - width * 0.5,
+                            neuron as f32 * vspace - (vspace * (layer - 1) as f32) * 0.5,
+                        )
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    // Neuron indices from an input to the strongest output, stepping back each
+    // time to the neuron that contributed the most
+    fn strongest_path(&self, trace: &Trace) -> Vec<usize> {
+        let trace = &trace.activations;
+        let out = trace.last().unwrap();
+        let mut j = (0..out.len())
+            .max_by(|&a, &b| out[a].total_cmp(&out[b]))
+            .unwrap_or(0);
+        let mut path = vec![j];
+        for i in (0..self.weights.len()).rev() {
+            j = (0..trace[i].len())
+                .max_by(|&a, &b| {
+                    let c = |k: usize| self.weights[i][(j, k)] * trace[i][k];
+                    c(a).total_cmp(&c(b))
+                })
+                .unwrap_or(0);
+            path.push(j);
+        }
+        path.reverse();
+        path
+    }
+
+    pub fn draw(&self, width: f32, height: f32, inputs: &[f32], outputs: &[f32], bias: bool) {
+        draw_rectangle_lines(-width * 0.5, -height * 0.5, width, height, 2., WHITE);
+
+        let layout = self.layout(width, height, bias);
+        let last = layout.len() - 1;
+        let trace = (inputs.len() > 1).then(|| self.trace(inputs));
+
+        for i in 1..layout.len() {
+            // Nothing feeds into a bias neuron
+            let targets = layout[i].len() - (bias && i != last) as usize;
+            for (k, p1) in layout[i - 1].iter().enumerate() {
+                for (j, p2) in layout[i].iter().take(targets).enumerate() {
+                    let weight = *self.weights[i - 1].index((j, k));
+                    let c = if weight < 0. { 0. } else { 1. };
+                    draw_line(
+                        p1.0,
+                        p1.1,
+                        p2.0,
+                        p2.1,
+                        1.5,
+                        Color::new(1., c, c, weight.abs()),
+                    );
+                }
+            }
+        }
+
+        if let Some(trace) = &trace {
+            let path = self.strongest_path(trace);
+            for i in 1..layout.len() {
+                let (p1, p2) = (layout[i - 1][path[i - 1]], layout[i][path[i]]);
+                draw_line(p1.0, p1.1, p2.0, p2.1, 4., YELLOW);
+            }
+        }
+
+        for (i, layer) in layout.iter().enumerate() {
+            for (j, p) in layer.iter().enumerate() {
                 draw_circle(p.0, p.1, 10., WHITE);
                 draw_circle(p.0, p.1, 8., BLACK);
-                draw_circle(
-                    p.0,
-                    p.1,
-                    8.,
-                    if i == 1 && inputs.len() > 1 {
-                        let c = if inputs[j] < 0. { 0. } else { 1. };
-                        Color::new(1., c, c, inputs[j].abs())
-                    } else {
-                        BLACK
-                    },
-                );
-                if i == 1 && inputs.len() > 1 {
+                if i == last {
+                    if !outputs.is_empty() {
+                        draw_circle(p.0, p.1, 8., Color::new(1., 1., 1., outputs[j]));
+                        draw_text(
+                            &format!("{:.2}", outputs[j]),
+                            p.0 + 14.,
+                            p.1 + 4.,
+                            16.,
+                            WHITE,
+                        );
+                    }
+                    continue;
+                }
+                // Bias neurons always output 1
+                let value = match &trace {
+                    Some(trace) => trace.activations[i].get(j).copied().unwrap_or(1.),
+                    None => continue,
+                };
+                let c = if value < 0. { 0. } else { 1. };
+                draw_circle(p.0, p.1, 8., Color::new(1., c, c, value.abs()));
+                if i == 0 {
                     draw_text(
-                        &format!("{:.2}", inputs[j]),
-                        p.0 - if inputs[j] < 0. { 50. } else { 42. },
+                        &format!("{:.2}", value),
+                        p.0 - if value < 0. { 50. } else { 42. },
                         p.1 + 4.,
                         16.,
                         WHITE,
@@ -173,20 +812,9 @@ impl NN {
                 }
             }
         }
-        for (j, p) in p2s.iter().enumerate() {
-            draw_circle(p.0, p.1, 10., WHITE);
-            draw_circle(p.0, p.1, 8., BLACK);
-            if !outputs.is_empty() {
-                draw_circle(p.0, p.1, 8., Color::new(1., 1., 1., outputs[j]));
-                draw_text(
-                    &format!("{:.2}", outputs[j]),
-                    p.0 + 14.,
-                    p.1 + 4.,
-                    16.,
-                    WHITE,
-                );
-            }
-        }
+
+        let width = width * 0.8;
+        let height = height * 0.8;
         draw_rectangle(width * 0.47, height * 0.47, 10., 10., RED);
         let params = TextParams {
             font_size: 40,
@@ -198,12 +826,334 @@ impl NN {
         draw_text_ex("+ve", width * 0.47 + 20., height * 0.47 + 30., params);
     }
 
-    pub fn export(&self) -> String {
-        serde_json::to_string(self).unwrap()
+    // Neurons take priority over the connections running into them
+    pub fn hover(&self, width: f32, height: f32, bias: bool, mouse: (f32, f32)) -> Option<Hover> {
+        let layout = self.layout(width, height, bias);
+        for (i, layer) in layout.iter().enumerate() {
+            for (j, p) in layer.iter().enumerate() {
+                if (p.0 - mouse.0).hypot(p.1 - mouse.1) <= 10. {
+                    return Some(Hover::Neuron(i, j));
+                }
+            }
+        }
+
+        let mut best = None;
+        let mut closest = 3.;
+        for (i, w) in self.weights.iter().enumerate() {
+            for j in 0..w.nrows() {
+                for k in 0..layout[i].len() {
+                    let d = segment_distance(layout[i][k], layout[i + 1][j], mouse);
+                    if d < closest {
+                        closest = d;
+                        best = Some(Hover::Weight(i, j, k));
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    // The connections feeding into a neuron and fed by it, largest magnitude
+    // first
+    pub fn strongest_weights(&self, i: usize, j: usize, n: usize) -> (Vec<Link>, Vec<Link>) {
+        let mut incoming = Vec::new();
+        if i > 0 && j < self.weights[i - 1].nrows() {
+            let w = &self.weights[i - 1];
+            incoming.extend((0..w.ncols()).map(|k| (i - 1, k, w[(j, k)])));
+        }
+        let mut outgoing = Vec::new();
+        if let Some(w) = self.weights.get(i) {
+            outgoing.extend((0..w.nrows()).map(|r| (i + 1, r, w[(r, j)])));
+        }
+        for list in [&mut incoming, &mut outgoing] {
+            list.sort_by(|a, b| b.2.abs().total_cmp(&a.2.abs()));
+            list.truncate(n);
+        }
+        (incoming, outgoing)
+    }
+
+    // Tooltip for whatever is under the pointer and a table of the selected
+    // neuron's strongest connections, drawn over the panel from draw
+    pub fn draw_inspector(
+        &self,
+        width: f32,
+        height: f32,
+        inputs: &[f32],
+        bias: bool,
+        mouse: (f32, f32),
+        selected: Option<(usize, usize)>,
+    ) {
+        let layout = self.layout(width, height, bias);
+        let trace = (inputs.len() > 1).then(|| self.trace(inputs));
+        let name = |i: usize, j: usize| {
+            if i + 1 < layout.len() && j + 1 == self.config[i] {
+                format!("L{} bias", i)
+            } else if i == 0 {
+                INPUT_NAMES.get(j).unwrap_or(&"?").to_string()
+            } else {
+                format!("L{} #{}", i, j)
+            }
+        };
+
+        if let Some((i, j)) = selected {
+            if let Some(p) = layout.get(i).and_then(|l| l.get(j)) {
+                draw_circle_lines(p.0, p.1, 13., 2., YELLOW);
+                let (incoming, outgoing) = self.strongest_weights(i, j, 5);
+                let x = -width * 0.5 + 6.;
+                let mut y = -height * 0.5 + 16.;
+                let mut line = |text: &str, color: Color| {
+                    draw_text(text, x, y, 16., color);
+                    y += 14.;
+                };
+                line(&name(i, j), YELLOW);
+                for (title, list) in [("in", &incoming), ("out", &outgoing)] {
+                    if list.is_empty() {
+                        continue;
+                    }
+                    line(title, GRAY);
+                    for &(l, n, w) in list.iter() {
+                        let c = if w < 0. { RED } else { WHITE };
+                        line(&format!(" {:>+.3} {}", w, name(l, n)), c);
+                    }
+                }
+            }
+        }
+
+        let text = match self.hover(width, height, bias, mouse) {
+            Some(Hover::Weight(i, j, k)) => format!(
+                "{} -> {}: {:.4}",
+                name(i, k),
+                name(i + 1, j),
+                self.weights[i][(j, k)]
+            ),
+            Some(Hover::Neuron(i, j)) => match &trace {
+                Some(trace) => {
+                    let sum = trace.sums[i].get(j).copied().unwrap_or(1.);
+                    let act = trace.activations[i].get(j).copied().unwrap_or(1.);
+                    if i == 0 || j >= trace.sums[i].len() {
+                        format!("{}: {:.4}", name(i, j), act)
+                    } else {
+                        format!("{}: sum {:.4} act {:.4}", name(i, j), sum, act)
+                    }
+                }
+                None => name(i, j),
+            },
+            None => return,
+        };
+        let size = measure_text(&text, None, 16, 1.);
+        let (x, y) = (mouse.0 + 12., mouse.1 - 8.);
+        // Keep the tooltip inside the panel
+        let x = x.min(width * 0.5 - size.width - 8.);
+        draw_rectangle(
+            x - 4.,
+            y - size.offset_y - 4.,
+            size.width + 8.,
+            size.height + 8.,
+            BLACK,
+        );
+        draw_rectangle_lines(
+            x - 4.,
+            y - size.offset_y - 4.,
+            size.width + 8.,
+            size.height + 8.,
+            1.,
+            WHITE,
+        );
+        draw_text(&text, x, y, 16., WHITE);
+    }
+
+    // Cuts a neuron off from the rest of the network. For an input this is
+    // the same as the input always reading 0.
+    pub fn zero_neuron(&mut self, i: usize, j: usize) {
+        if i > 0 && j < self.weights[i - 1].nrows() {
+            self.weights[i - 1].row_mut(j).fill(0.);
+        }
+        if let Some(w) = self.weights.get_mut(i) {
+            w.column_mut(j).fill(0.);
+        }
+        if let Some(r) = i.checked_sub(1).and_then(|l| self.recurrent.get_mut(l)) {
+            if j < r.nrows() {
+                r.row_mut(j).fill(0.);
+                r.column_mut(j).fill(0.);
+            }
+        }
+    }
+
+    // Hidden layer sizes without the bias neuron, as picked in the UI
+    pub fn hidden_layers(&self) -> Vec<usize> {
+        self.config
+            .iter()
+            .take(self.config.len() - 1)
+            .skip(1)
+            .map(|x| x - 1)
+            .collect()
     }
+}
+
+// Distance from p to the segment between a and b
+fn segment_distance(a: (f32, f32), b: (f32, f32), p: (f32, f32)) -> f32 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len = dx * dx + dy * dy;
+    let t = if len > 0. {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / len).clamp(0., 1.)
+    } else {
+        0.
+    };
+    (a.0 + t * dx - p.0).hypot(a.1 + t * dy - p.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_single_activation_models() {
+        for json in [
+            include_str!("../models/brain.json"),
+            include_str!("../models/model.json"),
+        ] {
+            let nn: NN = serde_json::from_str(json).unwrap();
+            assert_eq!(nn.config, vec![6, 7, 7, 4]);
+            assert_eq!(nn.activ_funcs, vec![ActivationFunc::ReLU; 3]);
+            assert_eq!(nn.mut_sigma, 1.);
+            assert_eq!(nn.init, WeightInit::He);
+            assert!(nn.recurrent.is_empty());
+        }
+
+        let json = r#"{"config":[3,2],"weights":[[[1.0,-1.0,0.5,2.0,-0.5,0.25],2,3]],"activ_func":"Tanh","mut_rate":0.1}"#;
+        let mut nn: NN = serde_json::from_str(json).unwrap();
+        assert_eq!(nn.activ_funcs, vec![ActivationFunc::Tanh]);
+        assert_eq!(nn.mut_rate, 0.1);
+        let mut outputs = [0.; 2];
+        nn.feed_forward_into(&[1., 2.], &mut outputs);
+        // Matrices are stored column by column
+        assert_eq!(
+            outputs,
+            [(1. + 1. - 0.5f32).tanh(), (-1. + 4. + 0.25f32).tanh()]
+        );
+    }
+
+    #[test]
+    fn batch_matches_one_at_a_time() {
+        let activs = |n| vec![ActivationFunc::Tanh; n];
+        let mut nets = vec![
+            NN::new(vec![5, 6, 4], 0.05, activs(2), WeightInit::He),
+            NN::new(vec![5, 3, 3, 4], 0.05, activs(3), WeightInit::He),
+            NN::new(vec![5, 6, 4], 0.05, activs(2), WeightInit::He),
+            NN::new(vec![5, 6, 4], 0.05, activs(2), WeightInit::He),
+        ];
+        nets[3].recurrent = vec![WeightInit::He.matrix(6, 6)];
+        let mut singles = nets.clone();
+        let mut ws = Workspace::default();
+        for step in 0..3 {
+            let inputs = (0..nets.len())
+                .map(|k| (0..5).map(|j| (k + j + step) as f32 * 0.1 - 0.3).collect())
+                .collect::<Vec<Vec<f32>>>();
+            let mut batched = vec![[0.; 4]; nets.len()];
+            NN::feed_forward_batch(
+                &mut ws,
+                nets.iter_mut()
+                    .zip(&inputs)
+                    .zip(&mut batched)
+                    .map(|((nn, x), y)| (nn, &x[..], &mut y[..])),
+            );
+            for ((nn, x), y) in singles.iter_mut().zip(&inputs).zip(&batched) {
+                let mut expected = [0.; 4];
+                nn.feed_forward_into(x, &mut expected);
+                assert_eq!(&expected, y);
+            }
+        }
+    }
+
+    #[test]
+    fn genome_round_trips() {
+        for recurrent in [false, true] {
+            let mut nn = NN::new(
+                vec![5, 6, 3, 4],
+                0.1,
+                vec![
+                    ActivationFunc::Tanh,
+                    ActivationFunc::ReLU,
+                    ActivationFunc::Sigmoid,
+                ],
+                WeightInit::He,
+            );
+            nn.mut_sigma = 0.5;
+            if recurrent {
+                nn.add_recurrence();
+            }
+            let genome = nn.genome();
+            assert_eq!(genome.len(), NN::genome_len(&nn.config, recurrent));
+            let copy = NN::from_genome(
+                nn.config.clone(),
+                nn.activ_funcs.clone(),
+                recurrent,
+                &genome,
+                nn.mut_rate,
+                nn.mut_sigma,
+            )
+            .unwrap();
+            assert_eq!(copy.config, nn.config);
+            assert_eq!(copy.weights, nn.weights);
+            assert_eq!(copy.recurrent, nn.recurrent);
+            assert_eq!(copy.activ_funcs, nn.activ_funcs);
+            assert_eq!((copy.mut_rate, copy.mut_sigma), (0.1, 0.5));
+
+            let mut zeroed = copy.clone();
+            zeroed.set_genome(&vec![0.; genome.len()]);
+            assert!(zeroed.genome().iter().all(|&w| w == 0.));
+            zeroed.set_genome(&genome);
+            assert_eq!(zeroed.weights, nn.weights);
+            assert_eq!(zeroed.recurrent, nn.recurrent);
+        }
+        assert!(NN::from_genome(
+            vec![3, 2],
+            vec![ActivationFunc::ReLU],
+            false,
+            &[0.; 5],
+            0.1,
+            1.
+        )
+        .is_err());
+        let e = NN::from_genome(
+            vec![3, 4, 2],
+            vec![ActivationFunc::ReLU],
+            false,
+            &[],
+            0.1,
+            1.,
+        )
+        .unwrap_err();
+        assert_eq!(e, "1 activation functions for 2 layers");
+    }
+
+    #[test]
+    fn genome_distances() {
+        use crate::genome::{behavioral, cosine, euclidean, probes};
+
+        assert_eq!(euclidean(&[1., 2.], &[4., 6.]), 5.);
+        assert_eq!(euclidean(&[1., 2.], &[1., 2.]), 0.);
+        assert!(cosine(&[1., 2.], &[2., 4.]).abs() < 1e-6);
+        assert!((cosine(&[1., 2.], &[-1., -2.]) - 2.).abs() < 1e-6);
+        assert!((cosine(&[1., 0.], &[0., 3.]) - 1.).abs() < 1e-6);
+        assert_eq!(cosine(&[0., 0.], &[1., 2.]), 1.);
+        assert_eq!(cosine(&[0., 0.], &[0., 0.]), 1.);
 
-    pub fn import(path: &str) -> NN {
-        let json = std::fs::read_to_string(path).expect("Unable to read file");
-        serde_json::from_str(&json).unwrap()
+        let probes = probes(50, 3);
+        let activs = vec![ActivationFunc::Tanh; 2];
+        let mut a = NN::new(vec![5, 6, 4], 0.1, activs.clone(), WeightInit::He);
+        a.add_recurrence();
+        assert_eq!(behavioral(&a, &a, &probes), 0.);
+        let mut b = a.clone();
+        b.set_genome(&vec![0.; b.genome().len()]);
+        let d = behavioral(&a, &b, &probes);
+        assert!(d > 0.);
+        // Symmetric, and every comparison starts from a blank state
+        assert_eq!(behavioral(&b, &a, &probes), d);
+        a.feed_forward_into(&probes[0], &mut [0.; 4]);
+        assert_eq!(behavioral(&a, &b, &probes), d);
+        // Networks of different shapes can still be compared
+        let c = NN::new(vec![5, 3, 4], 0.1, activs, WeightInit::He);
+        assert!(behavioral(&a, &c, &probes).is_finite());
     }
 }
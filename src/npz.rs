@@ -0,0 +1,516 @@
+use std::io::Read;
+
+use flate2::read::DeflateDecoder;
+use nalgebra::DMatrix;
+
+use crate::{
+    binary::crc32,
+    brain::ImportError,
+    nn::{ActivationFunc, WeightInit, NN},
+};
+
+// NumPy archives: a zip of .npy files. Written as
+//   layerK.npy      float32 (outputs, inputs + 1), the last column being the bias
+//   recurrentK.npy  float32 (size, size), only for recurrent networks
+//   config.npy      int64 layer sizes, not counting bias neurons
+//   activations.npy activation name of every layer but the input one
+//   mutation.npy    float32 [mut_rate, mut_sigma]
+pub const MAGIC: [u8; 4] = *b"PK\x03\x04";
+
+enum Array {
+    Float(Vec<usize>, Vec<f32>),
+    Int(Vec<i64>),
+    Str(Vec<String>),
+}
+
+fn npy(descr: &str, shape: &[usize], data: &[u8]) -> Vec<u8> {
+    let shape = match shape {
+        [n] => format!("({},)", n),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(|n| n.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        descr, shape
+    );
+    // Magic, version and length take 10 bytes, and the data should start
+    // 64-byte aligned
+    while (10 + header.len() + 1) % 64 != 0 {
+        header.push(' ');
+    }
+    header.push('\n');
+    let mut out = b"\x93NUMPY\x01\x00".to_vec();
+    out.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    out.extend_from_slice(header.as_bytes());
+    out.extend_from_slice(data);
+    out
+}
+
+fn floats(m: &DMatrix<f32>) -> Vec<u8> {
+    m.row_iter()
+        .flat_map(|r| r.iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<_>>())
+        .collect()
+}
+
+// Uncompressed, like np.savez
+fn zip(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut central = Vec::new();
+    for (name, data) in files {
+        let offset = out.len() as u32;
+        let mut fields = Vec::new();
+        fields.extend_from_slice(&20u16.to_le_bytes()); // version needed
+        fields.extend_from_slice(&0u16.to_le_bytes()); // flags
+        fields.extend_from_slice(&0u16.to_le_bytes()); // stored
+        fields.extend_from_slice(&0u16.to_le_bytes()); // time
+        fields.extend_from_slice(&0x21u16.to_le_bytes()); // 1980-01-01
+        fields.extend_from_slice(&crc32(data).to_le_bytes());
+        fields.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        fields.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes()); // extra length
+
+        out.extend_from_slice(&MAGIC);
+        out.extend_from_slice(&fields);
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(data);
+
+        central.extend_from_slice(b"PK\x01\x02");
+        central.extend_from_slice(&20u16.to_le_bytes()); // version made by
+        central.extend_from_slice(&fields);
+        central.extend_from_slice(&[0; 6]); // comment length, disk, internal attributes
+        central.extend_from_slice(&[0; 4]); // external attributes
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+    }
+    let start = out.len() as u32;
+    out.extend_from_slice(&central);
+    out.extend_from_slice(b"PK\x05\x06");
+    out.extend_from_slice(&[0; 4]); // disk numbers
+    out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    out.extend_from_slice(&start.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes()); // comment length
+    out
+}
+
+pub fn export(nn: &NN) -> Vec<u8> {
+    let mut files = Vec::new();
+    for (i, w) in nn.weights.iter().enumerate() {
+        let data = npy("<f4", &[w.nrows(), w.ncols()], &floats(w));
+        files.push((format!("layer{}.npy", i), data));
+    }
+    for (i, r) in nn.recurrent.iter().enumerate() {
+        let data = npy("<f4", &[r.nrows(), r.ncols()], &floats(r));
+        files.push((format!("recurrent{}.npy", i), data));
+    }
+    let sizes = nn
+        .weights
+        .iter()
+        .map(|w| w.ncols() - 1)
+        .chain(nn.weights.last().map(|w| w.nrows()))
+        .flat_map(|n| (n as i64).to_le_bytes())
+        .collect::<Vec<_>>();
+    files.push((
+        "config.npy".to_string(),
+        npy("<i8", &[nn.config.len()], &sizes),
+    ));
+    // Same names as in JSON models
+    let names = nn
+        .activ_funcs
+        .iter()
+        .map(|f| format!("{:?}", f))
+        .collect::<Vec<_>>();
+    let width = names.iter().map(|n| n.len()).max().unwrap_or(1);
+    let chars = names
+        .iter()
+        .flat_map(|n| {
+            let mut n = n.chars().map(|c| c as u32).collect::<Vec<_>>();
+            n.resize(width, 0);
+            n
+        })
+        .flat_map(|c| c.to_le_bytes())
+        .collect::<Vec<_>>();
+    files.push((
+        "activations.npy".to_string(),
+        npy(&format!("<U{}", width), &[names.len()], &chars),
+    ));
+    let mutation = [nn.mut_rate, nn.mut_sigma]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect::<Vec<_>>();
+    files.push(("mutation.npy".to_string(), npy("<f4", &[2], &mutation)));
+    zip(&files)
+}
+
+fn corrupt(e: &str) -> ImportError {
+    ImportError::Corrupt(e.to_string())
+}
+
+fn u16_at(b: &[u8], i: usize) -> Result<usize, ImportError> {
+    b.get(i..i + 2)
+        .map(|s| u16::from_le_bytes(s.try_into().unwrap()) as usize)
+        .ok_or(corrupt("archive is truncated"))
+}
+
+fn u32_at(b: &[u8], i: usize) -> Result<usize, ImportError> {
+    b.get(i..i + 4)
+        .map(|s| u32::from_le_bytes(s.try_into().unwrap()) as usize)
+        .ok_or(corrupt("archive is truncated"))
+}
+
+// Every file in the archive, found through the central directory
+fn unzip(b: &[u8]) -> Result<Vec<(String, Vec<u8>)>, ImportError> {
+    let end = (0..b.len().saturating_sub(21))
+        .rev()
+        .find(|&i| b[i..].starts_with(b"PK\x05\x06"))
+        .ok_or(corrupt("not a zip archive"))?;
+    let count = u16_at(b, end + 10)?;
+    let mut pos = u32_at(b, end + 16)?;
+    let mut files = Vec::new();
+    for _ in 0..count {
+        if !b.get(pos..).is_some_and(|s| s.starts_with(b"PK\x01\x02")) {
+            return Err(corrupt("bad central directory"));
+        }
+        let method = u16_at(b, pos + 10)?;
+        let size = u32_at(b, pos + 20)?;
+        let name_len = u16_at(b, pos + 28)?;
+        let skip = name_len + u16_at(b, pos + 30)? + u16_at(b, pos + 32)?;
+        let local = u32_at(b, pos + 42)?;
+        let name = b
+            .get(pos + 46..pos + 46 + name_len)
+            .ok_or(corrupt("archive is truncated"))?;
+        let name = String::from_utf8_lossy(name).to_string();
+        pos += 46 + skip;
+
+        let start = local + 30 + u16_at(b, local + 26)? + u16_at(b, local + 28)?;
+        let raw = b
+            .get(start..start + size)
+            .ok_or(corrupt("archive is truncated"))?;
+        let data = match method {
+            0 => raw.to_vec(),
+            8 => {
+                let mut data = Vec::new();
+                DeflateDecoder::new(raw)
+                    .read_to_end(&mut data)
+                    .map_err(|e| ImportError::Corrupt(format!("{}: {}", name, e)))?;
+                data
+            }
+            m => {
+                return Err(ImportError::Corrupt(format!(
+                    "{} uses compression {}",
+                    name, m
+                )))
+            }
+        };
+        files.push((name, data));
+    }
+    Ok(files)
+}
+
+// Value of `key` in a .npy header such as "{'descr': '<f4', 'shape': (2, 3), }"
+fn header_field<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let rest = header
+        .get(header.find(&format!("'{}':", key))? + key.len() + 3..)?
+        .trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')')? + 1
+    } else {
+        rest.find([',', '}'])?
+    };
+    Some(rest[..end].trim().trim_matches('\''))
+}
+
+fn parse_npy(name: &str, b: &[u8]) -> Result<Array, ImportError> {
+    let bad = |e: &str| ImportError::Corrupt(format!("{}: {}", name, e));
+    if !b.starts_with(b"\x93NUMPY") || b.len() < 10 {
+        return Err(bad("not a .npy file"));
+    }
+    let (len, start) = match b[6] {
+        1 => (u16_at(b, 8)?, 10),
+        _ => (u32_at(b, 8)?, 12),
+    };
+    let header = b
+        .get(start..start + len)
+        .ok_or(bad("header is truncated"))?;
+    let header = String::from_utf8_lossy(header);
+    let data = &b[start + len..];
+
+    let descr = header_field(&header, "descr").ok_or(bad("missing descr"))?;
+    let fortran = header_field(&header, "fortran_order") == Some("True");
+    let shape = header_field(&header, "shape")
+        .ok_or(bad("missing shape"))?
+        .trim_matches(['(', ')'])
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().map_err(|_| bad("bad shape")))
+        .collect::<Result<Vec<usize>, _>>()?;
+    let n = shape.iter().product::<usize>();
+    // Row-major order, whatever the file uses
+    let dims = (fortran && shape.len() == 2).then(|| (shape[0], shape[1]));
+    let order = |i: usize| match dims {
+        Some((r, c)) => (i % c) * r + i / c,
+        None => i,
+    };
+
+    let width = descr
+        .get(2..)
+        .and_then(|w| w.parse::<usize>().ok())
+        .ok_or(bad("bad descr"))?;
+    let size = if descr.as_bytes()[1] == b'U' {
+        width.saturating_mul(4)
+    } else {
+        width
+    };
+    if descr.starts_with('>') || n.checked_mul(size).is_none_or(|len| data.len() < len) {
+        return Err(bad("unsupported or truncated data"));
+    }
+    let item = |i: usize| &data[order(i) * size..(order(i) + 1) * size];
+    Ok(match &descr[1..] {
+        "f4" => Array::Float(
+            shape,
+            (0..n)
+                .map(|i| f32::from_le_bytes(item(i).try_into().unwrap()))
+                .collect(),
+        ),
+        "f8" => Array::Float(
+            shape,
+            (0..n)
+                .map(|i| f64::from_le_bytes(item(i).try_into().unwrap()) as f32)
+                .collect(),
+        ),
+        "i4" => Array::Int(
+            (0..n)
+                .map(|i| i32::from_le_bytes(item(i).try_into().unwrap()) as i64)
+                .collect(),
+        ),
+        "i8" => Array::Int(
+            (0..n)
+                .map(|i| i64::from_le_bytes(item(i).try_into().unwrap()))
+                .collect(),
+        ),
+        _ if descr.as_bytes()[1] == b'U' => Array::Str(
+            (0..n)
+                .map(|i| {
+                    item(i)
+                        .chunks_exact(4)
+                        .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+                        .take_while(|&c| c != 0)
+                        .filter_map(char::from_u32)
+                        .collect()
+                })
+                .collect(),
+        ),
+        _ => return Err(bad(&format!("unsupported dtype {}", descr))),
+    })
+}
+
+pub fn import(bytes: &[u8]) -> Result<NN, ImportError> {
+    let files = unzip(bytes)?;
+    let array = |name: &str| -> Result<Array, ImportError> {
+        let file = format!("{}.npy", name);
+        let (_, data) = files
+            .iter()
+            .find(|(n, _)| *n == file)
+            .ok_or(ImportError::Corrupt(format!("missing {}", file)))?;
+        parse_npy(&file, data)
+    };
+    let matrix = |name: &str| -> Result<DMatrix<f32>, ImportError> {
+        match array(name)? {
+            Array::Float(shape, data) if shape.len() == 2 => {
+                Ok(DMatrix::from_row_slice(shape[0], shape[1], &data))
+            }
+            _ => Err(ImportError::Shape(format!(
+                "{} must be a 2D float array",
+                name
+            ))),
+        }
+    };
+
+    let sizes = match array("config")? {
+        Array::Int(sizes) if sizes.len() >= 2 && sizes.iter().all(|&n| n > 0) => sizes,
+        _ => {
+            return Err(ImportError::Shape(
+                "config must hold the layer sizes".to_string(),
+            ))
+        }
+    };
+    let layers = sizes.len();
+    let config = sizes
+        .iter()
+        .enumerate()
+        .map(|(i, &n)| n as usize + (i + 1 < layers) as usize)
+        .collect::<Vec<_>>();
+    let activ_funcs = match array("activations")? {
+        Array::Str(names) if names.len() == layers - 1 => names
+            .iter()
+            .map(|name| {
+                ActivationFunc::ALL
+                    .into_iter()
+                    .find(|f| format!("{:?}", f) == *name)
+                    .ok_or(ImportError::Shape(format!("unknown activation {}", name)))
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => {
+            return Err(ImportError::Shape(format!(
+                "activations must hold {} names",
+                layers - 1
+            )))
+        }
+    };
+    let (mut_rate, mut_sigma) = match array("mutation") {
+        Ok(Array::Float(_, m)) if m.len() == 2 => (m[0], m[1]),
+        _ => (0.05, 1.),
+    };
+    let weights = (0..layers - 1)
+        .map(|i| matrix(&format!("layer{}", i)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let recurrent = if files.iter().any(|(n, _)| n == "recurrent0.npy") {
+        (0..layers - 2)
+            .map(|i| matrix(&format!("recurrent{}", i)))
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        Vec::new()
+    };
+    Ok(NN::from_parts(
+        config,
+        weights,
+        activ_funcs,
+        mut_rate,
+        mut_sigma,
+        recurrent,
+        WeightInit::default(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::DeflateEncoder, Compression};
+
+    use super::*;
+    use crate::brain::Brain;
+
+    fn nn(recurrent: bool) -> NN {
+        let mut nn = NN::new(
+            vec![5, 6, 3, 4],
+            0.1,
+            vec![
+                ActivationFunc::Tanh,
+                ActivationFunc::LeakyReLU,
+                ActivationFunc::Sigmoid,
+            ],
+            WeightInit::Uniform(0.5),
+        );
+        if recurrent {
+            nn.add_recurrence();
+        }
+        nn
+    }
+
+    fn assert_same(a: &NN, b: &NN) {
+        assert_eq!(a.config, b.config);
+        assert_eq!(a.weights, b.weights);
+        assert_eq!(a.recurrent, b.recurrent);
+        assert_eq!(a.activ_funcs, b.activ_funcs);
+        assert_eq!((a.mut_rate, a.mut_sigma), (b.mut_rate, b.mut_sigma));
+    }
+
+    #[test]
+    fn round_trips_networks() {
+        for recurrent in [false, true] {
+            let nn = nn(recurrent);
+            let bytes = export(&nn);
+            assert!(bytes.starts_with(&MAGIC));
+            let mut copy = import(&bytes).ok().unwrap();
+            assert_same(&nn, &copy);
+            let mut outputs = [0.; 4];
+            copy.feed_forward_into(&[0.5; 5], &mut outputs);
+            assert!(outputs.iter().all(|y| y.is_finite()));
+        }
+    }
+
+    #[test]
+    fn rejects_misshapen_recurrent_weights() {
+        let mut nn = nn(true);
+        nn.recurrent[1] = WeightInit::He.matrix(2, 2);
+        let copy = import(&export(&nn)).ok().unwrap();
+        match Brain::NN(copy).validate() {
+            Err(ImportError::Shape(e)) => {
+                assert_eq!(e, "layer 2 recurrent weights are 2x2, expected 3x3")
+            }
+            _ => panic!("expected a shape error"),
+        }
+    }
+
+    #[test]
+    fn reads_fortran_order_doubles() {
+        // np.asfortranarray(np.arange(6.).reshape(2, 3)) written with np.save
+        let data = [0f64, 3., 1., 4., 2., 5.]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut file = npy("<f8", &[2, 3], &data);
+        // Padded to the same header length
+        let at = file.windows(5).position(|w| w == b"False").unwrap();
+        file.splice(at..at + 5, *b"True ");
+        match parse_npy("fortran.npy", &file).ok().unwrap() {
+            Array::Float(shape, data) => {
+                assert_eq!(shape, [2, 3]);
+                assert_eq!(data, [0., 1., 2., 3., 4., 5.]);
+            }
+            _ => panic!("expected a float array"),
+        }
+
+        // And as a layer of a network, so the matrix is built from it row by row
+        let nn = NN::from_parts(
+            vec![3, 2],
+            vec![DMatrix::from_row_slice(2, 3, &[0., 1., 2., 3., 4., 5.])],
+            vec![ActivationFunc::Identity],
+            0.05,
+            1.,
+            Vec::new(),
+            WeightInit::default(),
+        );
+        let mut files = unzip(&export(&nn)).ok().unwrap();
+        files[0].1 = file;
+        assert_same(&nn, &import(&zip(&files)).ok().unwrap());
+    }
+
+    #[test]
+    fn reads_deflated_archives() {
+        // np.savez_compressed deflates every file. Only the method and the
+        // compressed size are read back, so the stored entries are patched.
+        let nn = nn(true);
+        let files = unzip(&export(&nn))
+            .ok()
+            .unwrap()
+            .into_iter()
+            .map(|(name, data)| {
+                let mut e = DeflateEncoder::new(Vec::new(), Compression::default());
+                e.write_all(&data).unwrap();
+                (name, e.finish().unwrap())
+            })
+            .collect::<Vec<_>>();
+        let mut bytes = zip(&files);
+        let headers = (0..bytes.len() - 4)
+            .filter(|&i| bytes[i..].starts_with(b"PK\x01\x02"))
+            .collect::<Vec<_>>();
+        assert_eq!(headers.len(), files.len());
+        for i in headers {
+            bytes[i + 10] = 8;
+            let local = u32_at(&bytes, i + 42).ok().unwrap();
+            bytes[local + 8] = 8;
+        }
+        assert_same(&nn, &import(&bytes).ok().unwrap());
+    }
+}
@@ -0,0 +1,543 @@
+use crate::nn::{ActivationFunc, NN};
+
+// Just enough of the protobuf wire format to write an ONNX ModelProto
+const IR_VERSION: u64 = 8;
+const OPSET: u64 = 13;
+const FLOAT: u64 = 1;
+const ATTR_FLOAT: u64 = 1;
+const ATTR_INT: u64 = 2;
+const ATTR_TENSOR: u64 = 4;
+
+fn varint(out: &mut Vec<u8>, mut x: u64) {
+    while x >= 0x80 {
+        out.push(x as u8 | 0x80);
+        x >>= 7;
+    }
+    out.push(x as u8);
+}
+
+fn int(out: &mut Vec<u8>, field: u64, x: u64) {
+    varint(out, field << 3);
+    varint(out, x);
+}
+
+fn bytes(out: &mut Vec<u8>, field: u64, b: &[u8]) {
+    varint(out, field << 3 | 2);
+    varint(out, b.len() as u64);
+    out.extend_from_slice(b);
+}
+
+fn float(out: &mut Vec<u8>, field: u64, x: f32) {
+    varint(out, field << 3 | 5);
+    out.extend_from_slice(&x.to_le_bytes());
+}
+
+fn tensor(name: &str, dims: &[usize], data: &[f32]) -> Vec<u8> {
+    let mut t = Vec::new();
+    for &d in dims {
+        int(&mut t, 1, d as u64);
+    }
+    int(&mut t, 2, FLOAT);
+    bytes(&mut t, 8, name.as_bytes());
+    let raw = data
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect::<Vec<_>>();
+    bytes(&mut t, 9, &raw);
+    t
+}
+
+// A float tensor of shape [batch, n], the batch size being left symbolic
+fn value_info(name: &str, n: usize) -> Vec<u8> {
+    let mut batch = Vec::new();
+    bytes(&mut batch, 2, b"N");
+    let mut width = Vec::new();
+    int(&mut width, 1, n as u64);
+    let mut shape = Vec::new();
+    bytes(&mut shape, 1, &batch);
+    bytes(&mut shape, 1, &width);
+    let mut tensor_type = Vec::new();
+    int(&mut tensor_type, 1, FLOAT);
+    bytes(&mut tensor_type, 2, &shape);
+    let mut ty = Vec::new();
+    bytes(&mut ty, 1, &tensor_type);
+    let mut v = Vec::new();
+    bytes(&mut v, 1, name.as_bytes());
+    bytes(&mut v, 2, &ty);
+    v
+}
+
+enum Attr<'a> {
+    Float(&'a str, f32),
+    Int(&'a str, u64),
+    Tensor(&'a str, Vec<u8>),
+}
+
+struct Graph {
+    nodes: Vec<Vec<u8>>,
+    initializers: Vec<Vec<u8>>,
+}
+
+impl Graph {
+    fn node(&mut self, op: &str, inputs: &[&str], output: &str, attrs: &[Attr]) {
+        let mut n = Vec::new();
+        for i in inputs {
+            bytes(&mut n, 1, i.as_bytes());
+        }
+        bytes(&mut n, 2, output.as_bytes());
+        bytes(&mut n, 3, format!("{}_{}", op, self.nodes.len()).as_bytes());
+        bytes(&mut n, 4, op.as_bytes());
+        for attr in attrs {
+            let mut a = Vec::new();
+            match attr {
+                Attr::Float(name, x) => {
+                    bytes(&mut a, 1, name.as_bytes());
+                    float(&mut a, 2, *x);
+                    int(&mut a, 20, ATTR_FLOAT);
+                }
+                Attr::Int(name, x) => {
+                    bytes(&mut a, 1, name.as_bytes());
+                    int(&mut a, 3, *x);
+                    int(&mut a, 20, ATTR_INT);
+                }
+                Attr::Tensor(name, t) => {
+                    bytes(&mut a, 1, name.as_bytes());
+                    bytes(&mut a, 5, t);
+                    int(&mut a, 20, ATTR_TENSOR);
+                }
+            }
+            bytes(&mut n, 5, &a);
+        }
+        self.nodes.push(n);
+    }
+
+    // Applies an activation to `x`, using several nodes for the ones ONNX has
+    // no operator for
+    fn activation(&mut self, f: ActivationFunc, x: &str, y: &str) {
+        let tmp = format!("{}_tmp", y);
+        match f {
+            ActivationFunc::ReLU => self.node("Relu", &[x], y, &[]),
+            ActivationFunc::Sigmoid => self.node("Sigmoid", &[x], y, &[]),
+            ActivationFunc::Tanh => self.node("Tanh", &[x], y, &[]),
+            ActivationFunc::LeakyReLU => {
+                self.node("LeakyRelu", &[x], y, &[Attr::Float("alpha", 0.01)])
+            }
+            ActivationFunc::Elu => self.node("Elu", &[x], y, &[Attr::Float("alpha", 1.)]),
+            ActivationFunc::Softsign => self.node("Softsign", &[x], y, &[]),
+            ActivationFunc::Gaussian => {
+                let neg = format!("{}_neg", y);
+                self.node("Mul", &[x, x], &tmp, &[]);
+                self.node("Neg", &[&tmp], &neg, &[]);
+                self.node("Exp", &[&neg], y, &[]);
+            }
+            ActivationFunc::Sine => self.node("Sin", &[x], y, &[]),
+            ActivationFunc::Step => {
+                let zero = format!("{}_zero", y);
+                self.node(
+                    "Constant",
+                    &[],
+                    &zero,
+                    &[Attr::Tensor("value", tensor("", &[], &[0.]))],
+                );
+                self.node("Greater", &[x, &zero], &tmp, &[]);
+                self.node("Cast", &[&tmp], y, &[Attr::Int("to", FLOAT)]);
+            }
+            ActivationFunc::Identity => self.node("Identity", &[x], y, &[]),
+            ActivationFunc::Swish => {
+                self.node("Sigmoid", &[x], &tmp, &[]);
+                self.node("Mul", &[x, &tmp], y, &[]);
+            }
+        }
+    }
+}
+
+// One Gemm per layer with the bias column split off into its own tensor, then
+// the layer's activation. Recurrent networks get an extra input and output per
+// hidden layer carrying its state between calls.
+pub fn export(nn: &NN) -> Vec<u8> {
+    let mut g = Graph {
+        nodes: Vec::new(),
+        initializers: Vec::new(),
+    };
+    let mut inputs = vec![value_info("input", nn.config[0] - 1)];
+    let mut outputs = Vec::new();
+    let mut x = "input".to_string();
+    for (i, w) in nn.weights.iter().enumerate() {
+        let cols = w.ncols() - 1;
+        // Row-major for ONNX, leaving out the bias column
+        let weights = w
+            .row_iter()
+            .flat_map(|r| r.iter().take(cols).copied().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let bias = w.column(cols).iter().copied().collect::<Vec<_>>();
+        let (wn, bn, z) = (format!("W{}", i), format!("B{}", i), format!("z{}", i));
+        g.initializers
+            .push(tensor(&wn, &[w.nrows(), cols], &weights));
+        g.initializers.push(tensor(&bn, &[w.nrows()], &bias));
+        g.node("Gemm", &[&x, &wn, &bn], &z, &[Attr::Int("transB", 1)]);
+
+        let mut pre = z;
+        if let Some(r) = nn.recurrent.get(i) {
+            let (rn, hin, rz, sum) = (
+                format!("R{}", i),
+                format!("state{}_in", i),
+                format!("rz{}", i),
+                format!("sum{}", i),
+            );
+            // Column-major data read as row-major is the transpose MatMul wants
+            let rw = r.iter().copied().collect::<Vec<_>>();
+            g.initializers
+                .push(tensor(&rn, &[r.ncols(), r.nrows()], &rw));
+            inputs.push(value_info(&hin, r.nrows()));
+            g.node("MatMul", &[&hin, &rn], &rz, &[]);
+            g.node("Add", &[&pre, &rz], &sum, &[]);
+            pre = sum;
+        }
+
+        let y = if i + 1 == nn.weights.len() {
+            "output".to_string()
+        } else if i < nn.recurrent.len() {
+            outputs.push(value_info(&format!("state{}_out", i), w.nrows()));
+            format!("state{}_out", i)
+        } else {
+            format!("h{}", i)
+        };
+        g.activation(nn.activ_funcs[i], &pre, &y);
+        x = y;
+    }
+    outputs.insert(0, value_info("output", *nn.config.last().unwrap()));
+
+    let mut graph = Vec::new();
+    for n in &g.nodes {
+        bytes(&mut graph, 1, n);
+    }
+    bytes(&mut graph, 2, b"genetic");
+    for t in &g.initializers {
+        bytes(&mut graph, 5, t);
+    }
+    for v in &inputs {
+        bytes(&mut graph, 11, v);
+    }
+    for v in &outputs {
+        bytes(&mut graph, 12, v);
+    }
+
+    let mut opset = Vec::new();
+    bytes(&mut opset, 1, b"");
+    int(&mut opset, 2, OPSET);
+    let mut model = Vec::new();
+    int(&mut model, 1, IR_VERSION);
+    bytes(&mut model, 2, b"genetic");
+    bytes(&mut model, 7, &graph);
+    bytes(&mut model, 8, &opset);
+    model
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::nn::WeightInit;
+
+    enum Field<'a> {
+        Int(u64),
+        Bytes(&'a [u8]),
+        Fixed32(f32),
+    }
+
+    fn read_varint(b: &[u8], pos: &mut usize) -> u64 {
+        let mut x = 0;
+        let mut shift = 0;
+        loop {
+            let byte = b[*pos];
+            *pos += 1;
+            x |= ((byte & 0x7f) as u64) << shift;
+            if byte < 0x80 {
+                return x;
+            }
+            shift += 7;
+        }
+    }
+
+    // Every field of one protobuf message by number
+    fn fields(b: &[u8]) -> Vec<(u64, Field<'_>)> {
+        let mut pos = 0;
+        let mut out = Vec::new();
+        while pos < b.len() {
+            let key = read_varint(b, &mut pos);
+            let field = match key & 7 {
+                0 => Field::Int(read_varint(b, &mut pos)),
+                2 => {
+                    let len = read_varint(b, &mut pos) as usize;
+                    pos += len;
+                    Field::Bytes(&b[pos - len..pos])
+                }
+                5 => {
+                    pos += 4;
+                    Field::Fixed32(f32::from_le_bytes(b[pos - 4..pos].try_into().unwrap()))
+                }
+                t => panic!("unexpected wire type {}", t),
+            };
+            out.push((key >> 3, field));
+        }
+        out
+    }
+
+    fn messages<'a>(fields: &[(u64, Field<'a>)], n: u64) -> Vec<&'a [u8]> {
+        fields
+            .iter()
+            .filter_map(|(k, f)| match f {
+                Field::Bytes(b) if *k == n => Some(*b),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn strings(fields: &[(u64, Field)], n: u64) -> Vec<String> {
+        messages(fields, n)
+            .iter()
+            .map(|b| String::from_utf8(b.to_vec()).unwrap())
+            .collect()
+    }
+
+    fn ints(fields: &[(u64, Field)], n: u64) -> Vec<u64> {
+        fields
+            .iter()
+            .filter_map(|(k, f)| match f {
+                Field::Int(x) if *k == n => Some(*x),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn floats(fields: &[(u64, Field)], n: u64) -> Vec<f32> {
+        fields
+            .iter()
+            .filter_map(|(k, f)| match f {
+                Field::Fixed32(x) if *k == n => Some(*x),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // A TensorProto's dims and raw float data
+    fn tensor_data(t: &[u8]) -> (Vec<u64>, Vec<f32>) {
+        let t = fields(t);
+        assert_eq!(ints(&t, 2), vec![FLOAT]);
+        let data = messages(&t, 9)[0]
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+        (ints(&t, 1), data)
+    }
+
+    #[derive(Default)]
+    struct Attrs {
+        ints: HashMap<String, u64>,
+        floats: HashMap<String, f32>,
+        tensors: HashMap<String, Vec<f32>>,
+    }
+
+    struct Node {
+        op: String,
+        inputs: Vec<String>,
+        output: String,
+        attrs: Attrs,
+    }
+
+    // Runs the graph on one sample, given the initializers decoded from the
+    // file and a value for each graph input, and returns every value computed
+    fn run(model: &[u8], feeds: &[(String, Vec<f32>)]) -> HashMap<String, Vec<f32>> {
+        let model = fields(model);
+        let graph = fields(messages(&model, 7)[0]);
+        let mut values = HashMap::new();
+        let mut dims = HashMap::new();
+        for t in messages(&graph, 5) {
+            let name = strings(&fields(t), 8).remove(0);
+            let (shape, data) = tensor_data(t);
+            dims.insert(name.clone(), shape);
+            values.insert(name, data);
+        }
+        for (name, x) in feeds {
+            values.insert(name.clone(), x.clone());
+        }
+        let nodes = messages(&graph, 1).into_iter().map(|n| {
+            let n = fields(n);
+            let mut attrs = Attrs::default();
+            for a in messages(&n, 5) {
+                let a = fields(a);
+                let name = strings(&a, 1).remove(0);
+                match ints(&a, 20)[0] {
+                    ATTR_INT => attrs.ints.insert(name, ints(&a, 3)[0]).is_none(),
+                    ATTR_FLOAT => attrs.floats.insert(name, floats(&a, 2)[0]).is_none(),
+                    ATTR_TENSOR => {
+                        let value = tensor_data(messages(&a, 5)[0]).1;
+                        attrs.tensors.insert(name, value).is_none()
+                    }
+                    t => panic!("unsupported attribute type {}", t),
+                };
+            }
+            Node {
+                op: strings(&n, 4).remove(0),
+                inputs: strings(&n, 1),
+                output: strings(&n, 2).remove(0),
+                attrs,
+            }
+        });
+        for node in nodes {
+            let x = |i: usize| &values[&node.inputs[i]];
+            let map = |f: &dyn Fn(f32) -> f32| x(0).iter().map(|&v| f(v)).collect::<Vec<_>>();
+            let y = match node.op.as_str() {
+                "Gemm" => {
+                    assert_eq!(node.attrs.ints["transB"], 1);
+                    let (w, cols) = (x(1), dims[&node.inputs[1]][1] as usize);
+                    x(2).iter()
+                        .enumerate()
+                        .map(|(j, &b)| {
+                            let mut z = 0.;
+                            for (k, &xk) in x(0).iter().enumerate() {
+                                z += w[j * cols + k] * xk;
+                            }
+                            z + b
+                        })
+                        .collect()
+                }
+                "MatMul" => {
+                    let (r, cols) = (x(1), dims[&node.inputs[1]][1] as usize);
+                    (0..cols)
+                        .map(|j| {
+                            let mut z = 0.;
+                            for (k, &hk) in x(0).iter().enumerate() {
+                                z += r[k * cols + j] * hk;
+                            }
+                            z
+                        })
+                        .collect()
+                }
+                "Add" => x(0).iter().zip(x(1)).map(|(a, b)| a + b).collect(),
+                "Mul" => x(0).iter().zip(x(1)).map(|(a, b)| a * b).collect(),
+                // Scalars broadcast against the other operand
+                "Greater" => {
+                    let b = x(1);
+                    x(0).iter()
+                        .enumerate()
+                        .map(|(i, a)| (*a > b[i % b.len()]) as u8 as f32)
+                        .collect()
+                }
+                "Cast" => {
+                    assert_eq!(node.attrs.ints["to"], FLOAT);
+                    x(0).clone()
+                }
+                "Constant" => node.attrs.tensors["value"].clone(),
+                "Identity" => x(0).clone(),
+                "Neg" => map(&|v| -v),
+                "Exp" => map(&|v| v.exp()),
+                "Sin" => map(&|v| v.sin()),
+                "Relu" => map(&|v| v.max(0.)),
+                "LeakyRelu" => {
+                    let alpha = node.attrs.floats["alpha"];
+                    map(&|v| if v < 0. { alpha * v } else { v })
+                }
+                "Elu" => {
+                    let alpha = node.attrs.floats["alpha"];
+                    map(&|v| if v < 0. { alpha * (v.exp() - 1.) } else { v })
+                }
+                "Softsign" => map(&|v| v / (1. + v.abs())),
+                "Tanh" => map(&|v| v.tanh()),
+                "Sigmoid" => map(&|v| 1. / (1. + (-v).exp())),
+                op => panic!("unsupported op {}", op),
+            };
+            values.insert(node.output, y);
+        }
+        values
+    }
+
+    fn network(recurrent: bool) -> NN {
+        let mut nn = NN::new(
+            vec![5, 6, 3, 4],
+            0.05,
+            vec![
+                ActivationFunc::Tanh,
+                ActivationFunc::ReLU,
+                ActivationFunc::Sigmoid,
+            ],
+            WeightInit::He,
+        );
+        if recurrent {
+            nn.recurrent = vec![WeightInit::He.matrix(6, 6), WeightInit::He.matrix(3, 3)];
+        }
+        nn
+    }
+
+    fn inputs(step: usize) -> Vec<f32> {
+        (0..5).map(|k| (k + step) as f32 * 0.3 - 0.7).collect()
+    }
+
+    #[test]
+    fn dense_matches_feed_forward() {
+        let mut nn = network(false);
+        let model = export(&nn);
+        for step in 0..3 {
+            let mut expected = [0.; 4];
+            nn.feed_forward_into(&inputs(step), &mut expected);
+            let values = run(&model, &[("input".to_string(), inputs(step))]);
+            assert_eq!(values["output"], expected);
+        }
+    }
+
+    #[test]
+    fn recurrent_matches_feed_forward() {
+        let mut nn = network(true);
+        let model = export(&nn);
+        let mut state = vec![vec![0.; 6], vec![0.; 3]];
+        for step in 0..2 {
+            let mut expected = [0.; 4];
+            nn.feed_forward_into(&inputs(step), &mut expected);
+            let mut feeds = vec![("input".to_string(), inputs(step))];
+            for (i, h) in state.iter().enumerate() {
+                feeds.push((format!("state{}_in", i), h.clone()));
+            }
+            let values = run(&model, &feeds);
+            assert_eq!(values["output"], expected);
+            for (i, h) in state.iter_mut().enumerate() {
+                *h = values[&format!("state{}_out", i)].clone();
+            }
+        }
+        assert_eq!(state, nn.state);
+    }
+
+    #[test]
+    fn every_activation_matches_feed_forward() {
+        // Swish is x * sigmoid(x) in the graph and x / (1 + e^-x) in the app,
+        // so results may differ in the last bit
+        for activs in ActivationFunc::ALL.chunks(3) {
+            for recurrent in [false, true] {
+                let mut config = vec![5];
+                config.extend(vec![6; activs.len() - 1]);
+                config.push(4);
+                let mut nn = NN::new(config, 0.05, activs.to_vec(), WeightInit::Uniform(1.));
+                if recurrent {
+                    nn.add_recurrence();
+                }
+                let model = export(&nn);
+                let mut state = vec![vec![0.; 6]; nn.recurrent.len()];
+                for step in 0..3 {
+                    let mut expected = [0.; 4];
+                    nn.feed_forward_into(&inputs(step), &mut expected);
+                    let mut feeds = vec![("input".to_string(), inputs(step))];
+                    for (i, h) in state.iter().enumerate() {
+                        feeds.push((format!("state{}_in", i), h.clone()));
+                    }
+                    let values = run(&model, &feeds);
+                    for (y, e) in values["output"].iter().zip(expected) {
+                        assert!((y - e).abs() <= 1e-6 * e.abs().max(1.), "{:?}", activs);
+                    }
+                    for (i, h) in state.iter_mut().enumerate() {
+                        *h = values[&format!("state{}_out", i)].clone();
+                    }
+                }
+            }
+        }
+    }
+}
@@ -4,9 +4,25 @@ use macroquad::{prelude::*, rand::gen_range};
 
 use crate::{
     asteroids::Asteroid,
-    nn::{ActivationFunc, NN},
+    brain::Brain,
+    nn::{layer_activs, ActivationFunc, Hover, WeightInit, NN},
     HEIGHT, WIDTH,
 };
+
+pub const INPUTS: usize = 5;
+pub const OUTPUTS: usize = 4;
+pub const INPUT_NAMES: [&str; INPUTS] = [
+    "asteroid distance",
+    "asteroid angle",
+    "relative velocity x",
+    "relative velocity y",
+    "rotation",
+];
+// The keys each output presses
+pub const OUTPUT_NAMES: [&str; OUTPUTS] = ["right", "left", "thrust", "shoot"];
+pub const DRAG: f32 = 0.001;
+pub const SHOT_INTERVAL: u32 = 18;
+
 #[derive(Default)]
 pub struct Player {
     pub pos: Vec2,
@@ -17,13 +33,15 @@ pub struct Player {
     drag: f32,
     bullets: Vec<Bullet>,
     asteroid: Option<Asteroid>,
-    inputs: Vec<f32>,
+    pub inputs: Vec<f32>,
     pub outputs: Vec<f32>,
+    // The keys held down last frame, by the brain or a human
+    pub keys: [bool; OUTPUTS],
     // asteroid_data: Vec<(f32, f32, f32)>,
     raycasts: Vec<f32>,
     last_shot: u32,
     shot_interval: u32,
-    pub brain: Option<NN>,
+    pub brain: Option<Brain>,
     alive: bool,
     pub lifespan: u32,
     pub shots: u32,
@@ -33,17 +51,24 @@ impl Player {
     pub fn new(
         config: Option<Vec<usize>>,
         mut_rate: Option<f32>,
-        activ: Option<ActivationFunc>,
+        activs: Option<Vec<ActivationFunc>>,
+        init: Option<WeightInit>,
     ) -> Self {
         Self {
             brain: match config {
                 Some(mut c) => {
+                    let activs = layer_activs(&c, &activs.unwrap());
                     c.retain(|&x| x != 0);
                     // Number of inputs
-                    c.insert(0, 5);
+                    c.insert(0, INPUTS);
                     // Number of outputs
-                    c.push(4);
-                    Some(NN::new(c, mut_rate.unwrap(), activ.unwrap()))
+                    c.push(OUTPUTS);
+                    Some(Brain::NN(NN::new(
+                        c,
+                        mut_rate.unwrap(),
+                        activs,
+                        init.unwrap_or_default(),
+                    )))
                 }
                 _ => None,
             },
@@ -51,8 +76,8 @@ impl Player {
             rot: 1.5 * PI,
 
             // Change scaling when passing inputs if this is changed
-            drag: 0.001,
-            shot_interval: 18,
+            drag: DRAG,
+            shot_interval: SHOT_INTERVAL,
             alive: true,
             shots: 4,
             outputs: vec![0.; 4],
@@ -119,14 +144,13 @@ impl Player {
         false
     }
 
-    pub fn update(&mut self) {
-        self.lifespan += 1;
-        self.last_shot += 1;
-        self.acc = 0.;
-        self.outputs = vec![0.; 4];
-        let mut keys = vec![false; 4];
+    // Fills in the inputs from the asteroid seen last frame. Returns false and
+    // clears the outputs if there was none.
+    pub fn sense(&mut self) -> bool {
+        self.outputs.fill(0.);
         if let Some(ast) = self.asteroid.as_ref() {
-            self.inputs = vec![
+            self.inputs.clear();
+            self.inputs.extend_from_slice(&[
                 (ast.pos - self.pos).length() / HEIGHT,
                 self.dir.angle_between(ast.pos - self.pos),
                 (ast.vel - self.vel).x * 0.6,
@@ -135,7 +159,7 @@ impl Player {
                 // self.vel.x / 8.,
                 // self.vel.y / 8.,
                 // self.rot / TAU as f32,
-            ];
+            ]);
             // self.inputs.append(self.raycasts.as_mut());
 
             // self.asteroid_data
@@ -150,36 +174,52 @@ impl Player {
             //         .collect::<Vec<_>>(),
             // );
 
-            if let Some(brain) = &self.brain {
-                self.outputs = brain.feed_forward(&self.inputs);
-                keys = self
-                    .outputs
-                    .iter()
-                    .map(|&x| {
-                        x > if brain.activ_func == ActivationFunc::Sigmoid {
-                            0.85
-                        } else {
-                            0.
-                        }
-                    })
-                    .collect();
+            return true;
+        }
+        false
+    }
+
+    pub fn think(&mut self) {
+        if let Some(brain) = &mut self.brain {
+            brain.feed_forward_into(&self.inputs, &mut self.outputs);
+        }
+    }
+
+    // Moves the ship according to the outputs, or the keyboard for humans
+    pub fn act(&mut self) {
+        self.lifespan += 1;
+        self.last_shot += 1;
+        self.acc = 0.;
+        let mut keys = [false; OUTPUTS];
+        if let Some(brain) = &self.brain {
+            let threshold = brain.output_activ().threshold();
+            for (key, &x) in keys.iter_mut().zip(&self.outputs) {
+                *key = x > threshold;
             }
+        } else {
+            keys = [
+                is_key_down(KeyCode::Right),
+                is_key_down(KeyCode::Left),
+                is_key_down(KeyCode::Up),
+                is_key_down(KeyCode::Space),
+            ];
         }
-        if keys[0] || self.brain.is_none() && is_key_down(KeyCode::Right) {
+        self.keys = keys;
+        if keys[0] {
             // RIGHT
             self.rot = (self.rot + 0.1 + TAU as f32) % TAU as f32;
             self.dir = vec2(self.rot.cos(), self.rot.sin());
         }
-        if keys[1] || self.brain.is_none() && is_key_down(KeyCode::Left) {
+        if keys[1] {
             // LEFT
             self.rot = (self.rot - 0.1 + TAU as f32) % TAU as f32;
             self.dir = vec2(self.rot.cos(), self.rot.sin());
         }
-        if keys[2] || self.brain.is_none() && is_key_down(KeyCode::Up) {
+        if keys[2] {
             // THROTTLE
             self.acc = 0.14;
         }
-        if keys[3] || self.brain.is_none() && is_key_down(KeyCode::Space) {
+        if keys[3] {
             if self.last_shot > self.shot_interval {
                 self.last_shot = 0;
                 self.shots += 1;
@@ -255,11 +295,35 @@ impl Player {
         }
     }
 
-    pub fn draw_brain(&self, width: f32, height: f32, bias: bool) {
+    pub fn threshold(&self) -> f32 {
+        self.brain
+            .as_ref()
+            .map_or(0., |brain| brain.output_activ().threshold())
+    }
+
+    pub fn draw_brain(
+        &self,
+        width: f32,
+        height: f32,
+        bias: bool,
+        mouse: (f32, f32),
+        selected: Option<(usize, usize)>,
+    ) {
         if let Some(brain) = &self.brain {
             brain.draw(width, height, &self.inputs, &self.outputs, bias);
+            brain.draw_inspector(width, height, &self.inputs, bias, mouse, selected);
         }
     }
+
+    pub fn brain_hover(
+        &self,
+        width: f32,
+        height: f32,
+        bias: bool,
+        mouse: (f32, f32),
+    ) -> Option<Hover> {
+        self.brain.as_ref()?.hover(width, height, bias, mouse)
+    }
 }
 
 struct Bullet {
@@ -1,7 +1,15 @@
 use macroquad::{prelude::*, rand::gen_range};
 
 use crate::{
-    nn::{ActivationFunc, NN},
+    brain::{Arch, Brain},
+    es::{Optimizer, Strategy},
+    genome,
+    lineage::{Lineage, Operator},
+    neat::Neat,
+    nn::{ActivationFunc, WeightInit, Workspace, NN},
+    player::{INPUTS, OUTPUTS},
+    schedule::{MutationSchedule, Schedule},
+    stop::StopReason,
     world::World,
     HEIGHT, WIDTH,
 };
@@ -12,6 +20,13 @@ pub enum AutoSwitch {
     BestAlive,
 }
 
+// A genome whose world is over, kept for breeding in steady-state mode
+struct Evaluated {
+    fitness: f32,
+    id: usize,
+    brain: Brain,
+}
+
 #[derive(Default)]
 pub struct Population {
     size: usize,
@@ -22,6 +37,22 @@ pub struct Population {
     pub track: usize,
     pub hlayers: Vec<usize>,
     pub auto_switch: Option<AutoSwitch>,
+    pub lineage: Lineage,
+    // Best genome of any finished generation
+    best: Option<(f32, Brain)>,
+    pub frames: u64,
+    pub elapsed: f64,
+    pub stopped: Option<StopReason>,
+    pub schedule: MutationSchedule,
+    // Replace each world as soon as it dies instead of waiting for the whole generation
+    pub steady: bool,
+    pool: Vec<Evaluated>,
+    births: usize,
+    // Shared by every dense network evaluated in a frame
+    workspace: Workspace,
+    pub optimizer: Optimizer,
+    // Set for anything but the genetic algorithm
+    strategy: Option<Strategy>,
 }
 
 impl Population {
@@ -29,29 +60,116 @@ impl Population {
         size: usize,
         auto_switch: Option<AutoSwitch>,
         hlayers: Vec<usize>,
-        mut_rate: f32,
-        activ: ActivationFunc,
+        arch: Arch,
+        schedule: MutationSchedule,
+        activs: Vec<ActivationFunc>,
+        init: WeightInit,
     ) -> Self {
         let mut s = Self {
             size,
-            hlayers: hlayers.clone(),
             worlds: (0..size)
-                .map(|_| World::new(Some(hlayers.clone()), Some(mut_rate), Some(activ)))
+                .map(|_| random_world(&hlayers, arch, &schedule, &activs, init))
                 .collect(),
+            hlayers,
             auto_switch,
             focus: true,
+            schedule,
             ..Default::default()
         };
+        for world in &mut s.worlds {
+            world.id = s.lineage.record(vec![], Operator::Random, 0);
+        }
+        s.apply_schedule();
+        s.worlds[0].track(true);
+        s
+    }
+
+    // Starts from the given models, padding up to `size` with mutated offspring
+    // of the seeds or with freshly initialized networks
+    pub fn seeded(
+        size: usize,
+        auto_switch: Option<AutoSwitch>,
+        seeds: Vec<Brain>,
+        pad_random: bool,
+        schedule: MutationSchedule,
+    ) -> Self {
+        let size = std::cmp::max(size, seeds.len());
+        let arch = seeds[0].arch();
+        let (hlayers, init) = match &seeds[0] {
+            Brain::NN(nn) => (nn.hidden_layers(), nn.init),
+            Brain::Neat(_) => (vec![], WeightInit::default()),
+        };
+        let activs = seeds[0].activ_funcs();
+        let mut lineage = Lineage::default();
+        let mut worlds = seeds
+            .iter()
+            .map(|brain| {
+                let mut w = World::simulate(brain.to_owned());
+                w.id = lineage.record(vec![], Operator::Loaded, 0);
+                w
+            })
+            .collect::<Vec<_>>();
+        while worlds.len() < size {
+            let w = if pad_random {
+                let mut w = random_world(&hlayers, arch, &schedule, &activs, init);
+                w.id = lineage.record(vec![], Operator::Random, 0);
+                w
+            } else {
+                let (a, b) = (gen_range(0, seeds.len()), gen_range(0, seeds.len()));
+                let mut new_brain = Brain::crossover(&seeds[a], &seeds[b]);
+                new_brain.set_mutation(schedule.rate(), schedule.sigma());
+                new_brain.mutate();
+                let mut w = World::simulate(new_brain);
+                w.id = lineage.record(
+                    vec![worlds[a].id, worlds[b].id],
+                    if a == b {
+                        Operator::Mutation
+                    } else {
+                        Operator::Crossover
+                    },
+                    0,
+                );
+                w
+            };
+            worlds.push(w);
+        }
+        let mut s = Self {
+            size,
+            hlayers,
+            worlds,
+            auto_switch,
+            lineage,
+            schedule,
+            focus: true,
+            ..Default::default()
+        };
+        s.apply_schedule();
         s.worlds[0].track(true);
         s
     }
 
     pub fn update(&mut self) {
+        self.frames += 1;
+        self.think();
+        // Evolution strategies need a whole generation's fitness at once
+        let steady = self.steady && self.strategy.is_none();
         let mut alive = false;
-        for world in &mut self.worlds {
-            if !world.over {
+        for i in 0..self.worlds.len() {
+            if !self.worlds[i].over {
                 alive = true;
-                world.update();
+                self.worlds[i].step();
+                if steady && self.worlds[i].over {
+                    self.retire(i);
+                }
+            }
+        }
+        // Breeding only starts once a full population has been evaluated
+        if steady && self.pool.len() >= self.size {
+            for i in 0..self.worlds.len() {
+                if self.worlds[i].over {
+                    self.replace(i);
+                    alive = true;
+                }
             }
         }
         if self.worlds[self.track].over {
@@ -68,6 +186,142 @@ impl Population {
         }
     }
 
+    // Feeds every living ship what it sees. Dense networks go through one
+    // batch, anything else is evaluated on its own.
+    fn think(&mut self) {
+        let batch = self.worlds.iter_mut().filter_map(|w| {
+            let p = &mut w.player;
+            if w.over || !p.sense() {
+                return None;
+            }
+            if !matches!(p.brain, Some(Brain::NN(_))) {
+                p.think();
+                return None;
+            }
+            match &mut p.brain {
+                Some(Brain::NN(nn)) => Some((nn, &p.inputs[..], &mut p.outputs[..])),
+                _ => None,
+            }
+        });
+        NN::feed_forward_batch(&mut self.workspace, batch);
+    }
+
+    // Switches optimizer, starting evolution strategies from the best genome
+    // so far with a freshly sampled generation
+    pub fn set_optimizer(&mut self, optimizer: Optimizer) -> Result<(), String> {
+        self.optimizer = Optimizer::Genetic;
+        self.strategy = None;
+        if optimizer == Optimizer::Genetic {
+            return Ok(());
+        }
+        if self.size < 2 {
+            return Err("Evolution strategies need a population of at least 2".to_string());
+        }
+        let template = match self.best_brain() {
+            Brain::NN(nn) => nn.clone(),
+            Brain::Neat(_) => return Err("Evolution strategies need a dense network".to_string()),
+        };
+        self.optimizer = optimizer;
+        self.strategy = Strategy::new(optimizer, template.genome(), self.size);
+        self.sample(&template);
+        Ok(())
+    }
+
+    // Replaces every world with a genome drawn from the strategy
+    fn sample(&mut self, template: &NN) {
+        let genomes = match &mut self.strategy {
+            Some(strategy) => strategy.ask(),
+            None => return,
+        };
+        self.worlds = genomes
+            .iter()
+            .map(|g| {
+                let mut nn = template.clone();
+                nn.set_genome(g);
+                let mut w = World::simulate(Brain::NN(nn));
+                w.id = self.lineage.record(vec![], Operator::Sampled, self.gen);
+                w
+            })
+            .collect();
+        self.apply_schedule();
+        self.worlds[0].track(true);
+        self.track = 0;
+    }
+
+    pub fn set_steady(&mut self, steady: bool) {
+        self.steady = steady;
+        self.pool.clear();
+        self.births = 0;
+    }
+
+    // Moves a dead world's genome into the pool, dropping the worst if it is full
+    fn retire(&mut self, i: usize) {
+        let world = &self.worlds[i];
+        self.lineage.set_fitness(world.id, world.fitness);
+        if self.best.as_ref().is_none_or(|(f, _)| world.fitness > *f) {
+            self.best = Some((world.fitness, world.see_brain().to_owned()));
+        }
+        self.pool.push(Evaluated {
+            fitness: world.fitness,
+            id: world.id,
+            brain: world.see_brain().to_owned(),
+        });
+        if self.pool.len() > self.size {
+            let worst = self
+                .pool
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.fitness.total_cmp(&b.fitness))
+                .map(|(i, _)| i)
+                .unwrap();
+            self.pool.swap_remove(worst);
+        }
+    }
+
+    // Starts a child bred from the pool in slot i. Every `size` births count as a generation.
+    fn replace(&mut self, i: usize) {
+        let fitness = self.pool.iter().map(|e| e.fitness).collect::<Vec<_>>();
+        let (a, b) = (roulette(&fitness), roulette(&fitness));
+        let (a, b) = if fitness[b] > fitness[a] {
+            (b, a)
+        } else {
+            (a, b)
+        };
+        let mut new_brain = Brain::crossover(&self.pool[a].brain, &self.pool[b].brain);
+        new_brain.set_mutation(self.schedule.rate(), self.schedule.sigma());
+        new_brain.mutate();
+        let mut w = World::simulate(new_brain);
+        w.id = self.lineage.record(
+            vec![self.pool[a].id, self.pool[b].id],
+            if a == b {
+                Operator::Mutation
+            } else {
+                Operator::Crossover
+            },
+            self.gen,
+        );
+        self.worlds[i] = w;
+
+        self.births += 1;
+        if self.births.is_multiple_of(self.size) {
+            self.gen += 1;
+            let best = fitness.iter().fold(0., |acc: f32, &f| acc.max(f));
+            match self.diversity() {
+                Some(d) => println!("Gen: {}, Fitness: {}, Diversity: {:.3}", self.gen, best, d),
+                None => println!("Gen: {}, Fitness: {}", self.gen, best),
+            }
+            self.schedule.step(self.gen, best);
+            self.apply_schedule();
+        }
+
+        if i == self.track {
+            self.worlds[i].track(true);
+            if let Some(auto_switch) = self.auto_switch {
+                self.track_best(auto_switch == AutoSwitch::Best);
+            }
+        }
+    }
+
     pub fn change_track(&mut self, pos: Vec2) {
         for i in 0..self.worlds.len() {
             if !self.worlds[i].over
@@ -101,15 +355,90 @@ impl Population {
         self.track = 0;
     }
 
+    // Mean distance of each genome from the population's average one, or None
+    // for NEAT genomes, which have no common weight space
+    pub fn diversity(&self) -> Option<f32> {
+        let genomes = self
+            .worlds
+            .iter()
+            .map(|w| match w.see_brain() {
+                Brain::NN(nn) => Some(nn.genome()),
+                Brain::Neat(_) => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let mut mean = vec![0.; genomes.first()?.len()];
+        for g in &genomes {
+            if g.len() != mean.len() {
+                return None;
+            }
+            for (m, x) in mean.iter_mut().zip(g) {
+                *m += x / genomes.len() as f32;
+            }
+        }
+        let total = genomes
+            .iter()
+            .map(|g| genome::euclidean(g, &mean))
+            .sum::<f32>();
+        Some(total / genomes.len() as f32)
+    }
+
+    pub fn best_fitness(&self) -> f32 {
+        self.worlds
+            .iter()
+            .map(|w| w.fitness)
+            .chain(self.best.as_ref().map(|(f, _)| *f))
+            .fold(0., f32::max)
+    }
+
+    pub fn best_brain(&self) -> &Brain {
+        let current = self
+            .worlds
+            .iter()
+            .max_by(|a, b| a.fitness.total_cmp(&b.fitness))
+            .unwrap();
+        match &self.best {
+            Some((fitness, brain)) if *fitness > current.fitness => brain,
+            _ => current.see_brain(),
+        }
+    }
+
     pub fn change_mut(&mut self, mut_rate: f32) {
+        self.schedule.base_rate = mut_rate;
+        self.apply_schedule();
+    }
+
+    pub fn change_sigma(&mut self, sigma: f32) {
+        self.schedule.base_sigma = sigma;
+        self.apply_schedule();
+    }
+
+    // Keeps the evolved population, picking the new schedule up at the current generation
+    pub fn change_schedule(&mut self, schedule: Schedule) {
+        self.schedule =
+            MutationSchedule::new(schedule, self.schedule.base_rate, self.schedule.base_sigma);
+        self.schedule.step(self.gen, self.best_fitness());
+        self.apply_schedule();
+    }
+
+    fn apply_schedule(&mut self) {
         for world in &mut self.worlds {
-            world.player.brain.as_mut().unwrap().mut_rate = mut_rate;
+            world
+                .player
+                .brain
+                .as_mut()
+                .unwrap()
+                .set_mutation(self.schedule.rate(), self.schedule.sigma());
         }
     }
 
-    pub fn change_activ(&mut self, activ: ActivationFunc) {
+    pub fn change_activ(&mut self, activs: &[ActivationFunc]) {
         for world in &mut self.worlds {
-            world.player.brain.as_mut().unwrap().activ_func = activ;
+            world
+                .player
+                .brain
+                .as_mut()
+                .unwrap()
+                .set_activ_funcs(&self.hlayers, activs);
         }
     }
 
@@ -148,40 +477,128 @@ impl Population {
 
     pub fn next_gen(&mut self) {
         let total = self.worlds.iter().fold(0., |acc, x| acc + x.fitness);
+        // In the order the strategy sampled them, before sorting
+        let fitness = self.worlds.iter().map(|w| w.fitness).collect::<Vec<_>>();
+        for world in &self.worlds {
+            self.lineage.set_fitness(world.id, world.fitness);
+        }
         self.worlds
             .sort_by(|a, b| b.fitness.partial_cmp(&a.fitness).unwrap());
         for i in &self.worlds {
             println!("Fitness: {}", i.fitness);
         }
-        println!("Gen: {}, Fitness: {}", self.gen, self.worlds[0].fitness);
+        match self.diversity() {
+            Some(d) => println!(
+                "Gen: {}, Fitness: {}, Diversity: {:.3}",
+                self.gen, self.worlds[0].fitness, d
+            ),
+            None => println!("Gen: {}, Fitness: {}", self.gen, self.worlds[0].fitness),
+        }
+        if self
+            .best
+            .as_ref()
+            .is_none_or(|(f, _)| self.worlds[0].fitness > *f)
+        {
+            self.best = Some((
+                self.worlds[0].fitness,
+                self.worlds[0].see_brain().to_owned(),
+            ));
+        }
+        self.schedule.step(self.gen, self.worlds[0].fitness);
+        if let Some(strategy) = &mut self.strategy {
+            strategy.tell(&fitness);
+            if let Brain::NN(nn) = self.worlds[0].see_brain().to_owned() {
+                self.sample(&nn);
+                return;
+            }
+        }
         let mut new_worlds = (0..std::cmp::max(1, self.size / 20))
-            .map(|i| World::simulate(self.worlds[i].see_brain().to_owned()))
+            .map(|i| {
+                let mut w = World::simulate(self.worlds[i].see_brain().to_owned());
+                w.id = self
+                    .lineage
+                    .record(vec![self.worlds[i].id], Operator::Elite, self.gen);
+                w
+            })
             .collect::<Vec<_>>();
         while new_worlds.len() < self.size {
             let rands = (gen_range(0., total), gen_range(0., total));
             let mut sum = 0.;
             let (mut a, mut b) = (None, None);
-            for world in &self.worlds {
+            for (i, world) in self.worlds.iter().enumerate() {
                 sum += world.fitness;
                 if a.is_none() && sum >= rands.0 {
-                    a = Some(world.see_brain());
+                    a = Some(i);
                 }
                 if b.is_none() && sum >= rands.1 {
-                    b = Some(world.see_brain());
+                    b = Some(i);
                 }
             }
-            if a.is_none() {
-                a = Some(self.worlds.last().unwrap().see_brain());
-            }
-            if b.is_none() {
-                b = Some(self.worlds.last().unwrap().see_brain());
-            }
-            let mut new_brain = NN::crossover(a.unwrap(), b.unwrap());
+            let a = a.unwrap_or(self.worlds.len() - 1);
+            let b = b.unwrap_or(self.worlds.len() - 1);
+            // Worlds are sorted, so the lower index is the fitter parent
+            let (a, b) = (a.min(b), a.max(b));
+            let mut new_brain =
+                Brain::crossover(self.worlds[a].see_brain(), self.worlds[b].see_brain());
+            new_brain.set_mutation(self.schedule.rate(), self.schedule.sigma());
             new_brain.mutate();
-            new_worlds.push(World::simulate(new_brain));
+            let mut w = World::simulate(new_brain);
+            w.id = self.lineage.record(
+                vec![self.worlds[a].id, self.worlds[b].id],
+                if a == b {
+                    Operator::Mutation
+                } else {
+                    Operator::Crossover
+                },
+                self.gen,
+            );
+            new_worlds.push(w);
         }
         self.worlds = new_worlds;
+        self.apply_schedule();
         self.worlds[0].track(true);
         self.track = 0;
     }
 }
+
+fn roulette(fitness: &[f32]) -> usize {
+    let rand = gen_range(0., fitness.iter().sum::<f32>());
+    let mut sum = 0.;
+    for (i, f) in fitness.iter().enumerate() {
+        sum += f;
+        if sum >= rand {
+            return i;
+        }
+    }
+    fitness.len() - 1
+}
+
+// A world with a freshly initialized brain of the given architecture. Dense
+// and recurrent networks use the given hidden layers.
+fn random_world(
+    hlayers: &[usize],
+    arch: Arch,
+    schedule: &MutationSchedule,
+    activs: &[ActivationFunc],
+    init: WeightInit,
+) -> World {
+    if arch == Arch::Neat {
+        return World::simulate(Brain::Neat(Neat::new(
+            INPUTS,
+            OUTPUTS,
+            schedule.rate(),
+            activs[0],
+            *activs.last().unwrap(),
+        )));
+    }
+    let mut w = World::new(
+        Some(hlayers.to_vec()),
+        Some(schedule.rate()),
+        Some(activs.to_vec()),
+        Some(init),
+    );
+    if let (Arch::Recurrent, Some(Brain::NN(nn))) = (arch, &mut w.player.brain) {
+        nn.add_recurrence();
+    }
+    w
+}
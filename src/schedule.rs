@@ -0,0 +1,149 @@
+use std::f32::consts::PI;
+
+// Fraction of the base rate and sigma that decaying schedules bottom out at
+const FLOOR: f32 = 0.1;
+// Applied to the current factor each time the plateau patience runs out
+const PLATEAU_DECAY: f32 = 0.5;
+
+// How the mutation rate and sigma change over a run. The parameter is the
+// number of generations the schedule works over.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Schedule {
+    #[default]
+    Constant,
+    // Reaches the floor after n generations
+    Linear(i32),
+    // Halves every n generations
+    Exponential(i32),
+    // Half a cosine wave down to the floor over n generations
+    Cosine(i32),
+    // Decays whenever the best fitness hasn't improved for n generations
+    Plateau(i32),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MutationSchedule {
+    pub schedule: Schedule,
+    pub base_rate: f32,
+    pub base_sigma: f32,
+    factor: f32,
+    best: f32,
+    stale: i32,
+}
+
+impl Default for MutationSchedule {
+    fn default() -> Self {
+        MutationSchedule::new(Schedule::Constant, 0.05, 1.)
+    }
+}
+
+impl Schedule {
+    pub const NAMES: [&'static str; 5] = ["Constant", "Linear", "Exp", "Cosine", "Plateau"];
+
+    pub fn from_index(i: usize, gens: i32) -> Self {
+        match i {
+            1 => Schedule::Linear(gens),
+            2 => Schedule::Exponential(gens),
+            3 => Schedule::Cosine(gens),
+            4 => Schedule::Plateau(gens),
+            _ => Schedule::Constant,
+        }
+    }
+}
+
+impl MutationSchedule {
+    pub fn new(schedule: Schedule, base_rate: f32, base_sigma: f32) -> Self {
+        Self {
+            schedule,
+            base_rate,
+            base_sigma,
+            factor: 1.,
+            best: 0.,
+            stale: 0,
+        }
+    }
+
+    pub fn rate(&self) -> f32 {
+        self.base_rate * self.factor
+    }
+
+    pub fn sigma(&self) -> f32 {
+        self.base_sigma * self.factor
+    }
+
+    // Called once per generation with the best fitness of the finished one
+    pub fn step(&mut self, gen: i32, best: f32) {
+        let t = |n: i32| (gen as f32 / n.max(1) as f32).min(1.);
+        self.factor = match self.schedule {
+            Schedule::Constant => 1.,
+            Schedule::Linear(n) => 1. - (1. - FLOOR) * t(n),
+            Schedule::Exponential(n) => 0.5f32.powf(gen as f32 / n.max(1) as f32).max(FLOOR),
+            Schedule::Cosine(n) => FLOOR + (1. - FLOOR) * 0.5 * (1. + (PI * t(n)).cos()),
+            Schedule::Plateau(n) => {
+                if best > self.best {
+                    self.best = best;
+                    self.stale = 0;
+                } else {
+                    self.stale += 1;
+                }
+                if self.stale >= n.max(1) {
+                    self.stale = 0;
+                    (self.factor * PLATEAU_DECAY).max(FLOOR)
+                } else {
+                    self.factor
+                }
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn factors(schedule: Schedule, best: &[f32]) -> Vec<f32> {
+        let mut s = MutationSchedule::new(schedule, 0.2, 2.);
+        best.iter()
+            .enumerate()
+            .map(|(gen, &b)| {
+                s.step(gen as i32, b);
+                assert!((s.sigma() / s.rate() - 10.).abs() < 1e-4);
+                s.rate() / 0.2
+            })
+            .collect()
+    }
+
+    fn close(a: &[f32], b: &[f32]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-6)
+    }
+
+    #[test]
+    fn decays_to_the_floor() {
+        let flat = [0.; 6];
+        assert_eq!(factors(Schedule::Constant, &flat), [1.; 6]);
+        assert!(close(
+            &factors(Schedule::Linear(4), &flat),
+            &[1., 0.775, 0.55, 0.325, 0.1, 0.1]
+        ));
+        assert!(close(
+            &factors(Schedule::Exponential(1), &flat),
+            &[1., 0.5, 0.25, 0.125, 0.1, 0.1]
+        ));
+        let cosine = factors(Schedule::Cosine(4), &flat);
+        assert!(close(&[cosine[0], cosine[2], cosine[4]], &[1., 0.55, 0.1]));
+        assert!(cosine.windows(2).all(|w| w[0] >= w[1]));
+    }
+
+    #[test]
+    fn plateau_decays_only_when_stale() {
+        // Improving every generation keeps the full rate, then each 2
+        // generations without improvement halve it down to the floor
+        let best = [
+            1., 2., 3., 3., 3., 3., 3., 4., 4., 4., 4., 4., 4., 4., 4., 4.,
+        ];
+        assert!(close(
+            &factors(Schedule::Plateau(2), &best),
+            &[1., 1., 1., 1., 0.5, 0.5, 0.25, 0.25, 0.25, 0.125, 0.125, 0.1, 0.1, 0.1, 0.1, 0.1]
+        ));
+    }
+}
@@ -0,0 +1,57 @@
+use std::fmt;
+
+use crate::population::Population;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StopConditions {
+    pub target_fitness: Option<f32>,
+    pub max_gens: Option<i32>,
+    // Seconds of training, not counting time spent paused
+    pub max_time: Option<f64>,
+    pub max_frames: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    TargetFitness(f32),
+    MaxGens(i32),
+    MaxTime(f64),
+    MaxFrames(u64),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::TargetFitness(x) => write!(f, "reached target fitness {:.2}", x),
+            StopReason::MaxGens(x) => write!(f, "reached {} generations", x),
+            StopReason::MaxTime(x) => write!(f, "trained for {:.0} seconds", x),
+            StopReason::MaxFrames(x) => write!(f, "simulated {} frames", x),
+        }
+    }
+}
+
+impl StopConditions {
+    pub fn check(&self, pop: &Population) -> Option<StopReason> {
+        if let Some(target) = self.target_fitness {
+            if pop.best_fitness() >= target {
+                return Some(StopReason::TargetFitness(target));
+            }
+        }
+        if let Some(max) = self.max_gens {
+            if pop.gen >= max {
+                return Some(StopReason::MaxGens(max));
+            }
+        }
+        if let Some(max) = self.max_time {
+            if pop.elapsed >= max {
+                return Some(StopReason::MaxTime(max));
+            }
+        }
+        if let Some(max) = self.max_frames {
+            if pop.frames >= max {
+                return Some(StopReason::MaxFrames(max));
+            }
+        }
+        None
+    }
+}
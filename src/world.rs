@@ -1,9 +1,19 @@
 use crate::{
     asteroids::{Asteroid, AsteroidSize},
-    nn::{ActivationFunc, NN},
+    brain::Brain,
+    model::{Metadata, Model},
+    nn::{ActivationFunc, WeightInit},
     player::Player,
 };
-use macroquad::{prelude::*, rand::gen_range};
+use macroquad::{
+    prelude::*,
+    rand::{self, gen_range},
+};
+
+// Frames between new asteroids aimed at the player
+pub const SPAWN_INTERVAL: u32 = 200;
+// Episodes in World::evaluate are cut off after this many frames
+const EVAL_FRAMES: u32 = 10000;
 
 #[derive(Default)]
 pub struct World {
@@ -13,6 +23,8 @@ pub struct World {
     pub over: bool,
     pub fitness: f32,
     pub track: bool,
+    // Genome id in the population's lineage
+    pub id: usize,
     color: Color,
 }
 
@@ -20,11 +32,12 @@ impl World {
     pub fn new(
         hlayers: Option<Vec<usize>>,
         mut_rate: Option<f32>,
-        activ: Option<ActivationFunc>,
+        activs: Option<Vec<ActivationFunc>>,
+        init: Option<WeightInit>,
     ) -> Self {
         Self {
             color: Color::new(1., 1., 1., if hlayers.is_none() { 0.8 } else { 0.4 }),
-            player: Player::new(hlayers, mut_rate, activ),
+            player: Player::new(hlayers, mut_rate, activs, init),
             score: 1.,
             asteroids: vec![
                 Asteroid::new_to(vec2(0., 0.), 1.5, AsteroidSize::Large),
@@ -36,13 +49,34 @@ impl World {
             ..Default::default()
         }
     }
-    pub fn simulate(brain: NN) -> Self {
-        let mut w = World::new(None, None, None);
+    pub fn simulate(mut brain: Brain) -> Self {
+        let mut w = World::new(None, None, None, None);
+        brain.reset_state();
         w.player.brain = Some(brain);
         w.color = Color::new(1., 1., 1., 0.4);
         w
     }
 
+    // Mean fitness over a fixed set of episodes, the same ones for the same
+    // seed, so different networks can be compared fairly
+    pub fn evaluate(brain: &Brain, episodes: u32, seed: u64) -> f32 {
+        // The episodes reseed macroquad's generator, which has no way to read
+        // its state back, so it is reseeded afterwards from its own stream
+        let resume = (rand::rand() as u64) << 32 | rand::rand() as u64;
+        let total = (0..episodes)
+            .map(|e| {
+                rand::srand(seed.wrapping_add(e as u64));
+                let mut w = World::simulate(brain.clone());
+                while !w.over && w.player.lifespan < EVAL_FRAMES {
+                    w.update();
+                }
+                w.fitness
+            })
+            .sum::<f32>();
+        rand::srand(resume);
+        total / episodes.max(1) as f32
+    }
+
     pub fn track(&mut self, track: bool) {
         self.track = track;
         self.color = if track {
@@ -52,17 +86,27 @@ impl World {
         };
     }
 
-    pub fn see_brain(&self) -> &NN {
+    pub fn see_brain(&self) -> &Brain {
         self.player.brain.as_ref().unwrap()
     }
 
-    pub fn export_brain(&self, path: &str) {
-        let json = self.player.brain.as_ref().unwrap().export();
-        std::fs::write(path, json).expect("Unable to write file");
+    pub fn export_brain(&self, path: &str, meta: Metadata) -> std::io::Result<()> {
+        Model::new(self.see_brain().clone(), meta).save(path)
+    }
+
+    // Returns whether the player saw an asteroid, and so had inputs, this frame
+    pub fn update(&mut self) -> bool {
+        let sensed = self.player.sense();
+        if sensed {
+            self.player.think();
+        }
+        self.step();
+        sensed
     }
 
-    pub fn update(&mut self) {
-        self.player.update();
+    // Advances everything once the player has decided what to do this frame
+    pub fn step(&mut self) {
+        self.player.act();
         let mut to_add: Vec<Asteroid> = Vec::new();
         for asteroid in &mut self.asteroids {
             asteroid.update();
@@ -115,7 +159,7 @@ impl World {
         // }) < self.max_asteroids
         //     || self.player.lifespan % 200 == 0
         // {
-        if self.player.lifespan % 200 == 0 {
+        if self.player.lifespan.is_multiple_of(SPAWN_INTERVAL) {
             self.asteroids
                 .push(Asteroid::new_to(self.player.pos, 1.5, AsteroidSize::Large));
         }
@@ -152,25 +196,26 @@ impl World {
         draw_line(p1.x, p1.y, p2.x, p2.y, 2., WHITE);
         draw_line(p1.x, p1.y, p3.x, p3.y, 2., WHITE);
         draw_line(p4.x, p4.y, p5.x, p5.y, 2., WHITE);
-        if self.player.outputs[2] > 0. && (gen_range(0., 1.) < 0.4 || self.over) {
+        let threshold = self.player.threshold();
+        if self.player.outputs[2] > threshold && (gen_range(0., 1.) < 0.4 || self.over) {
             draw_triangle_lines(p6, p7, p8, 2., WHITE);
         }
         let l1 = scale * vec2(30., 0.) + offset;
         let l2 = scale * vec2(25., -5.) + offset;
         let l3 = scale * vec2(25., 5.) + offset;
-        if self.player.outputs[0] > 0. {
+        if self.player.outputs[0] > threshold {
             draw_line(l1.x, l1.y, l2.x, l2.y, 2., WHITE);
             draw_line(l1.x, l1.y, l3.x, l3.y, 2., WHITE);
         }
         let l1 = -scale * vec2(30., 0.) + offset;
         let l2 = -scale * vec2(25., -5.) + offset;
         let l3 = -scale * vec2(25., 5.) + offset;
-        if self.player.outputs[1] > 0. {
+        if self.player.outputs[1] > threshold {
             draw_line(l1.x, l1.y, l2.x, l2.y, 2., WHITE);
             draw_line(l1.x, l1.y, l3.x, l3.y, 2., WHITE);
         }
         let l1 = -scale * vec2(0., 35.) + offset;
-        if self.player.outputs[3] > 0. {
+        if self.player.outputs[3] > threshold {
             draw_circle(l1.x, l1.y, 5., WHITE);
             draw_circle(l1.x, l1.y, 3.5, BLACK);
         }